@@ -39,8 +39,12 @@ fn main() {
     let shader_ver = ShaderVersion::Default;
     // On linux use GLES SL 100+, like so:
     // let shader_ver = ShaderVersion::Adaptive;
+    // Or let the painter pick the shaders that suit the context:
+    // let shader_ver = ShaderVersion::Auto;
     let (mut painter, mut egui_state) =
         egui_backend::with_sdl2(&window, shader_ver, DpiScaling::Default);
+    // Where the window can't be multisampled, the painter can antialias egui on its own:
+    // painter.set_msaa_samples(4);
     let egui_ctx = egui::Context::default();
     let mut event_pump = sdl_context.event_pump().unwrap();
 
@@ -114,6 +118,8 @@ fn main() {
 
         let paint_jobs = egui_ctx.tessellate(shapes, pixels_per_point);
         painter.paint_jobs(None, textures_delta, paint_jobs);
+        // Answer screenshot requests while the frame is still in the back buffer.
+        egui_state.process_viewport_output(&painter, &viewport_output);
         window.gl_swap_window();
 
         let repaint_after = viewport_output
@@ -0,0 +1,142 @@
+use egui_backend::egui::FullOutput;
+use egui_backend::sdl2::video::GLProfile;
+use egui_backend::viewports::Viewports;
+use egui_backend::{egui, gl, sdl2};
+use egui_backend::{sdl2::event::Event, DpiScaling, ShaderVersion};
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+// Alias the backend to something less mouthful
+use egui_sdl2_gl as egui_backend;
+
+const SCREEN_WIDTH: u32 = 800;
+const SCREEN_HEIGHT: u32 = 600;
+
+fn main() {
+    let sdl_context = sdl2::init().unwrap();
+    let video_subsystem = sdl_context.video().unwrap();
+    let gl_attr = video_subsystem.gl_attr();
+    gl_attr.set_context_profile(GLProfile::Core);
+    gl_attr.set_double_buffer(true);
+
+    let window = video_subsystem
+        .window("Demo: Egui viewports", SCREEN_WIDTH, SCREEN_HEIGHT)
+        .opengl()
+        .resizable()
+        .build()
+        .unwrap();
+
+    // All viewport windows are painted with this context
+    let _ctx = window.gl_create_context().unwrap();
+    let (painter, mut egui_state) =
+        egui_backend::with_sdl2(&window, ShaderVersion::Default, DpiScaling::Default);
+    let painter = Rc::new(RefCell::new(painter));
+    let egui_ctx = egui::Context::default();
+    let viewports = Viewports::new(&egui_ctx, &window, painter.clone());
+    let mut event_pump = sdl_context.event_pump().unwrap();
+
+    // Deferred viewports may run without the rest of the app, so their state is shared
+    let show_deferred = Arc::new(AtomicBool::new(false));
+    let mut show_immediate = false;
+    let mut counter = 0;
+
+    let start_time = Instant::now();
+
+    'running: loop {
+        unsafe {
+            gl::ClearColor(0.3, 0.6, 0.3, 1.0);
+            gl::Clear(gl::COLOR_BUFFER_BIT);
+        }
+
+        egui_state.input.time = Some(start_time.elapsed().as_secs_f64());
+        egui_ctx.begin_pass(egui_state.input.take());
+
+        egui::CentralPanel::default().show(&egui_ctx, |ui| {
+            let mut deferred = show_deferred.load(Ordering::Relaxed);
+            ui.checkbox(&mut deferred, "Show deferred viewport");
+            show_deferred.store(deferred, Ordering::Relaxed);
+            ui.checkbox(&mut show_immediate, "Show immediate viewport");
+            ui.label(format!("Counter: {}", counter));
+        });
+
+        if show_deferred.load(Ordering::Relaxed) {
+            let show_deferred = show_deferred.clone();
+            egui_ctx.show_viewport_deferred(
+                egui::ViewportId::from_hash_of("deferred_viewport"),
+                egui::ViewportBuilder::default()
+                    .with_title("Deferred viewport")
+                    .with_inner_size([300.0, 200.0]),
+                move |ctx, _class| {
+                    egui::CentralPanel::default().show(ctx, |ui| {
+                        ui.label("This viewport runs on its own.");
+                    });
+                    if ctx.input(|i| i.viewport().close_requested()) {
+                        show_deferred.store(false, Ordering::Relaxed);
+                    }
+                },
+            );
+        }
+
+        if show_immediate {
+            egui_ctx.show_viewport_immediate(
+                egui::ViewportId::from_hash_of("immediate_viewport"),
+                egui::ViewportBuilder::default()
+                    .with_title("Immediate viewport")
+                    .with_inner_size([300.0, 200.0]),
+                |ctx, _class| {
+                    egui::CentralPanel::default().show(ctx, |ui| {
+                        ui.label("This viewport runs with the root viewport.");
+                        if ui.button("Increment").clicked() {
+                            counter += 1;
+                        }
+                    });
+                    if ctx.input(|i| i.viewport().close_requested()) {
+                        show_immediate = false;
+                    }
+                },
+            );
+        }
+
+        let FullOutput {
+            platform_output,
+            textures_delta,
+            shapes,
+            pixels_per_point,
+            viewport_output,
+        } = egui_ctx.end_pass();
+
+        egui_state.process_output(&window, &platform_output);
+
+        let paint_jobs = egui_ctx.tessellate(shapes, pixels_per_point);
+        painter
+            .borrow_mut()
+            .paint_jobs(None, textures_delta, paint_jobs);
+        window.gl_swap_window();
+
+        // Open, paint and close the other windows
+        viewports.update(&egui_ctx, &viewport_output);
+
+        if let Some(event) = event_pump.wait_event_timeout(5) {
+            match event {
+                Event::Quit { .. } => break 'running,
+                _ => {
+                    if !viewports.process_input(&event) {
+                        egui_state.process_input(&window, event, &mut painter.borrow_mut());
+                    }
+                }
+            }
+        }
+        for event in event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. } => break 'running,
+                _ => {
+                    if !viewports.process_input(&event) {
+                        egui_state.process_input(&window, event, &mut painter.borrow_mut());
+                    }
+                }
+            }
+        }
+    }
+}
@@ -3,16 +3,20 @@ precision mediump float;
 #endif
 
 uniform sampler2D u_sampler;
-#if defined(GL_ES) || __VERSION__ < 140
-varying vec4 v_rgba;
-varying vec2 v_tc;
-#else
+uniform bool u_texture_linear;
+uniform bool u_dither;
+uniform bool u_gamma_space;
+#if !defined(GL_ES) && __VERSION__ >= 140 || defined(GL_ES) && __VERSION__ >= 300
 in vec4 v_rgba;
 in vec2 v_tc;
 out vec4 f_color;
+#define texture2D texture
+#else
+varying vec4 v_rgba;
+varying vec2 v_tc;
+#define f_color gl_FragColor
 #endif
 
-#ifdef GL_ES
 // 0-255 sRGB  from  0-1 linear
 vec3 srgb_from_linear(vec3 rgb) {
   bvec3 cutoff = lessThan(rgb, vec3(0.0031308));
@@ -25,7 +29,6 @@ vec4 srgba_from_linear(vec4 rgba) {
   return vec4(srgb_from_linear(rgba.rgb), 255.0 * rgba.a);
 }
 
-#if __VERSION__ < 300
 // 0-1 linear  from  0-255 sRGB
 vec3 linear_from_srgb(vec3 srgb) {
   bvec3 cutoff = lessThan(srgb, vec3(10.31475));
@@ -37,37 +40,76 @@ vec3 linear_from_srgb(vec3 srgb) {
 vec4 linear_from_srgba(vec4 srgba) {
   return vec4(linear_from_srgb(srgba.rgb), srgba.a / 255.0);
 }
-#endif
-#endif
 
-#ifdef GL_ES
-void main() {
-#if __VERSION__ < 300
-  // We must decode the colors, since WebGL doesn't come with sRGBA textures:
-  vec4 texture_rgba = linear_from_srgba(texture2D(u_sampler, v_tc) * 255.0);
-#else
-  // The texture is set up with `SRGB8_ALPHA8`, so no need to decode here!
+// Interleaved gradient noise in [0, 1), from Jimenez 2014.
+float interleaved_gradient_noise(vec2 n) {
+  float f = 0.06711056 * n.x + 0.00583715 * n.y;
+  return fract(52.9829189 * fract(f));
+}
+
+// Dithers 0-255 gamma encoded, premultiplied colors to hide banding in 8 bit framebuffers.
+// The noise stays under half a step so flat colors stay flat, and is scaled by alpha so
+// transparent pixels stay transparent.
+vec3 dither(vec3 srgb, float alpha) {
+  float noise = (interleaved_gradient_noise(gl_FragCoord.xy) - 0.5) * 0.95;
+  return clamp(srgb + noise * alpha, 0.0, 255.0 * alpha);
+}
+
+// Keeps all the math in gamma space like egui_glow, for framebuffers that store and blend
+// the gamma encoded values as they are. `v_rgba` is gamma encoded too then.
+vec4 gamma_space_rgba() {
   vec4 texture_rgba = texture2D(u_sampler, v_tc);
-#endif
+  if (u_texture_linear) {
+    texture_rgba = srgba_from_linear(texture_rgba) / 255.0;
+  }
+  vec4 rgba = v_rgba * texture_rgba;
+  if (u_dither) {
+    rgba.rgb = dither(rgba.rgb * 255.0, rgba.a) / 255.0;
+  }
+  return rgba;
+}
 
-  /// Multiply vertex color with texture color (in linear space).
-  gl_FragColor = v_rgba * texture_rgba;
+// Without an sRGB framebuffer (GLES, or GL 2.x without the extension) the shader has to
+// do the sRGB conversions itself.
+#if defined(GL_ES) || defined(NO_FRAMEBUFFER_SRGB)
+void main() {
+  if (u_gamma_space) {
+    f_color = gamma_space_rgba();
+    return;
+  }
+  // We must decode the colors, since the textures are uploaded as plain RGBA:
+  vec4 texture_rgba = texture2D(u_sampler, v_tc);
+  if (!u_texture_linear) {
+    texture_rgba = linear_from_srgba(texture_rgba * 255.0);
+  }
 
   // We must gamma-encode again since WebGL doesn't support linear blending in the framebuffer.
-  gl_FragColor = srgba_from_linear(v_rgba * texture_rgba) / 255.0;
+  vec4 srgba = srgba_from_linear(v_rgba * texture_rgba);
+  if (u_dither) {
+    srgba.rgb = dither(srgba.rgb, srgba.a / 255.0);
+  }
+  f_color = srgba / 255.0;
 
   // WebGL doesn't support linear blending in the framebuffer,
   // so we apply this hack to at least get a bit closer to the desired blending:
-  gl_FragColor.a = pow(gl_FragColor.a, 1.6); // Empiric nonsense
+  f_color.a = pow(f_color.a, 1.6); // Empiric nonsense
 }
 #else
 void main() {
-  // The texture sampler is sRGB aware, and OpenGL already expects linear rgba output
-  // so no need for any sRGB conversions here:
-#if __VERSION__ < 140
-  gl_FragColor = v_rgba * texture2D(u_sampler, v_tc);
-#else
-  f_color = v_rgba * texture(u_sampler, v_tc);
-#endif
+  if (u_gamma_space) {
+    f_color = gamma_space_rgba();
+    return;
+  }
+  // The textures are uploaded as plain RGBA, so decode them unless they are linear already.
+  // The framebuffer encodes the linear output itself.
+  vec4 texture_rgba = texture2D(u_sampler, v_tc);
+  if (!u_texture_linear) {
+    texture_rgba = linear_from_srgba(texture_rgba * 255.0);
+  }
+  f_color = v_rgba * texture_rgba;
+  if (u_dither) {
+    // Dither the gamma encoded values, which is what the framebuffer stores.
+    f_color.rgb = linear_from_srgb(dither(srgb_from_linear(f_color.rgb), f_color.a));
+  }
 }
 #endif
\ No newline at end of file
@@ -1,4 +1,4 @@
-#if !defined(GL_ES) && __VERSION__ >= 140
+#if !defined(GL_ES) && __VERSION__ >= 140 || defined(GL_ES) && __VERSION__ >= 300
 #define I in
 #define O out
 #define V(x) x
@@ -12,6 +12,7 @@
 precision mediump float;
 #endif
 uniform vec2 u_screen_size;
+uniform bool u_gamma_space;
 I vec2 a_pos;
 I vec4 a_srgba; // 0-255 sRGB
 I vec2 a_tc;
@@ -32,7 +33,12 @@ vec4 linear_from_srgba(vec4 srgba) {
 
 void main() {
   gl_Position = vec4(2.0 * a_pos.x / u_screen_size.x - 1.0, 1.0 - 2.0 * a_pos.y / u_screen_size.y, 0.0, 1.0);
-  // egui encodes vertex colors in gamma spaces, so we must decode the colors here:
-  v_rgba = linear_from_srgba(a_srgba);
+  // egui encodes vertex colors in gamma spaces, so we must decode the colors here,
+  // unless the fragment shader works in gamma space:
+  if (u_gamma_space) {
+    v_rgba = a_srgba / 255.0;
+  } else {
+    v_rgba = linear_from_srgba(a_srgba);
+  }
   v_tc = a_tc;
 }
\ No newline at end of file
@@ -0,0 +1,21 @@
+// Copies the resolved MSAA frame onto the target pixel for pixel. The colors are already
+// premultiplied and encoded like the target, so this only undoes the decoding of sRGB
+// textures when the framebuffer won't encode them again.
+uniform sampler2D u_sampler;
+uniform bool u_encode_srgb;
+out vec4 f_color;
+
+// 0-1 sRGB  from  0-1 linear
+vec3 srgb_from_linear(vec3 rgb) {
+  bvec3 cutoff = lessThan(rgb, vec3(0.0031308));
+  vec3 lower = rgb * vec3(12.92);
+  vec3 higher = vec3(1.055) * pow(rgb, vec3(1.0 / 2.4)) - vec3(0.055);
+  return mix(higher, lower, vec3(cutoff));
+}
+
+void main() {
+  f_color = texelFetch(u_sampler, ivec2(gl_FragCoord.xy), 0);
+  if (u_encode_srgb) {
+    f_color.rgb = srgb_from_linear(f_color.rgb);
+  }
+}
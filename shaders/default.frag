@@ -1,5 +1,8 @@
 #version 150
 uniform sampler2D u_sampler;
+uniform bool u_texture_linear;
+uniform bool u_dither;
+uniform bool u_gamma_space;
 in vec4 v_rgba;
 in vec2 v_tc;
 out vec4 f_color;
@@ -22,8 +25,45 @@ vec3 linear_from_srgb(vec3 srgb) {
 vec4 linear_from_srgba(vec4 srgba) {
     return vec4(linear_from_srgb(srgba.rgb), srgba.a / 255.0);
 }
+// Interleaved gradient noise in [0, 1), from Jimenez 2014.
+float interleaved_gradient_noise(vec2 n) {
+    float f = 0.06711056 * n.x + 0.00583715 * n.y;
+    return fract(52.9829189 * fract(f));
+}
+
+// Dithers 0-255 gamma encoded, premultiplied colors to hide banding in 8 bit framebuffers.
+// The noise stays under half a step so flat colors stay flat, and is scaled by alpha so
+// transparent pixels stay transparent.
+vec3 dither(vec3 srgb, float alpha) {
+    float noise = (interleaved_gradient_noise(gl_FragCoord.xy) - 0.5) * 0.95;
+    return clamp(srgb + noise * alpha, 0.0, 255.0 * alpha);
+}
+// Keeps all the math in gamma space like egui_glow, for framebuffers that store and blend
+// the gamma encoded values as they are. `v_rgba` is gamma encoded too then.
+vec4 gamma_space_rgba() {
+    vec4 texture_rgba = texture(u_sampler, v_tc);
+    if (u_texture_linear) {
+        texture_rgba = srgba_from_linear(texture_rgba) / 255.0;
+    }
+    vec4 rgba = v_rgba * texture_rgba;
+    if (u_dither) {
+        rgba.rgb = dither(rgba.rgb * 255.0, rgba.a) / 255.0;
+    }
+    return rgba;
+}
 void main() {
-    // Need to convert from SRGBA to linear.
-    vec4 texture_rgba = linear_from_srgba(texture(u_sampler, v_tc) * 255.0);
+    if (u_gamma_space) {
+        f_color = gamma_space_rgba();
+        return;
+    }
+    vec4 texture_rgba = texture(u_sampler, v_tc);
+    // Need to convert from SRGBA to linear, unless the texture is linear already.
+    if (!u_texture_linear) {
+        texture_rgba = linear_from_srgba(texture_rgba * 255.0);
+    }
     f_color = v_rgba * texture_rgba;
+    if (u_dither) {
+        // Dither the gamma encoded values, which is what the framebuffer stores.
+        f_color.rgb = linear_from_srgb(dither(srgb_from_linear(f_color.rgb), f_color.a));
+    }
 }
\ No newline at end of file
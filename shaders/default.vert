@@ -1,5 +1,6 @@
 #version 150
 uniform vec2 u_screen_size;
+uniform bool u_gamma_space;
 in vec2 a_pos;
 in vec4 a_srgba; // 0-255 sRGB
 in vec2 a_tc;
@@ -21,6 +22,10 @@ void main() {
         1.0 - 2.0 * a_pos.y / u_screen_size.y,
         0.0,
         1.0);
-    v_rgba = linear_from_srgba(a_srgba);
+    if (u_gamma_space) {
+        v_rgba = a_srgba / 255.0;
+    } else {
+        v_rgba = linear_from_srgba(a_srgba);
+    }
     v_tc = a_tc;
 }
\ No newline at end of file
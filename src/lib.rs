@@ -6,9 +6,11 @@ pub use egui;
 pub use gl;
 pub use sdl2;
 pub mod painter;
+pub mod viewports;
+pub mod world_panel;
 #[cfg(feature = "use_epi")]
 pub use epi;
-use painter::Painter;
+use painter::{Painter, PainterError};
 #[cfg(feature = "use_epi")]
 use std::time::Instant;
 use {
@@ -73,6 +75,9 @@ pub enum ShaderVersion {
     Default,
     /// support GLSL 140+ and GLES SL 300.
     Adaptive,
+    /// Picks the shaders and their `#version` from the live GL context, covering desktop core,
+    /// compatibility and GLES contexts. See [`painter::Painter::shader_variant`] for the result.
+    Auto,
 }
 
 pub struct EguiStateHandler {
@@ -88,6 +93,15 @@ pub fn with_sdl2(
     shader_ver: ShaderVersion,
     scale: DpiScaling,
 ) -> (Painter, EguiStateHandler) {
+    try_with_sdl2(window, shader_ver, scale).unwrap_or_else(|err| panic!("{}", err))
+}
+
+/// Like [`with_sdl2`], but returns an error if the painter can't be created.
+pub fn try_with_sdl2(
+    window: &sdl2::video::Window,
+    shader_ver: ShaderVersion,
+    scale: DpiScaling,
+) -> Result<(Painter, EguiStateHandler), PainterError> {
     let standard_dpi = 96.0;
     let display_dpi =
         window
@@ -101,9 +115,9 @@ pub fn with_sdl2(
         DpiScaling::Default => default_scale,
         DpiScaling::Custom(custom) => default_scale * custom,
     };
-    let painter = painter::Painter::new(window, scale, shader_ver);
+    let painter = painter::Painter::try_new(window, scale, shader_ver)?;
     let state_handler = EguiStateHandler::new(&painter);
-    (painter, state_handler)
+    Ok((painter, state_handler))
 }
 
 impl EguiStateHandler {
@@ -156,6 +170,33 @@ impl EguiStateHandler {
         }
         translate_cursor(&mut self.fused_cursor, egui_output.cursor_icon);
     }
+
+    /// Handles the viewport commands in `viewport_output` that need the painter.
+    ///
+    /// Call this after [`Painter::paint_jobs`] and before swapping the window, so that the back
+    /// buffer still holds the frame that was just painted. Screenshots requested with
+    /// [`ViewportCommand::Screenshot`] are read back from it and delivered as
+    /// [`egui::Event::Screenshot`] with the next frame's input. Only the root viewport is handled
+    /// here, [`viewports::Viewports`] answers the screenshots of the other viewports.
+    pub fn process_viewport_output(
+        &mut self,
+        painter: &Painter,
+        viewport_output: &ViewportIdMap<ViewportOutput>,
+    ) {
+        let Some(output) = viewport_output.get(&ViewportId::ROOT) else {
+            return;
+        };
+        for command in &output.commands {
+            if let ViewportCommand::Screenshot(user_data) = command {
+                let image = painter.read_screen_rgba(painter.screen_rect);
+                self.input.events.push(egui::Event::Screenshot {
+                    viewport_id: ViewportId::ROOT,
+                    user_data: user_data.clone(),
+                    image: std::sync::Arc::new(image),
+                });
+            }
+        }
+    }
 }
 
 pub fn input_to_egui(
@@ -166,7 +207,6 @@ pub fn input_to_egui(
 ) {
     use sdl2::event::Event::*;
 
-    let pixels_per_point = painter.pixels_per_point;
     if event.get_window_id() != Some(window.id()) {
         return;
     }
@@ -180,6 +220,29 @@ pub fn input_to_egui(
             state.input.screen_rect = Some(painter.screen_rect);
         }
 
+        _ => translate_event(
+            window,
+            event,
+            painter.pixels_per_point,
+            painter.surface_rect().origin,
+            state,
+        ),
+    }
+}
+
+/// Turns an SDL event of `window` into egui input. Window size changes are left to the caller,
+/// since they affect whatever is painted into the window. Pointer positions are made relative
+/// to `origin`, the top-left corner of egui's surface in the window.
+pub(crate) fn translate_event(
+    window: &sdl2::video::Window,
+    event: sdl2::event::Event,
+    pixels_per_point: f32,
+    origin: (i32, i32),
+    state: &mut EguiStateHandler,
+) {
+    use sdl2::event::Event::*;
+
+    match event {
         //MouseButonLeft pressed is the only one needed by egui
         MouseButtonDown { mouse_btn, .. } => {
             let mouse_btn = match mouse_btn {
@@ -217,7 +280,10 @@ pub fn input_to_egui(
         }
 
         MouseMotion { x, y, .. } => {
-            state.pointer_pos = pos2(x as f32 / pixels_per_point, y as f32 / pixels_per_point);
+            state.pointer_pos = pos2(
+                (x - origin.0) as f32 / pixels_per_point,
+                (y - origin.1) as f32 / pixels_per_point,
+            );
             state
                 .input
                 .events
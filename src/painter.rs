@@ -1,22 +1,41 @@
 extern crate gl;
 extern crate sdl2;
 use crate::ShaderVersion;
-use ahash::AHashMap;
+use ahash::{AHashMap, AHashSet};
 use core::mem;
 use core::ptr;
 use core::str;
 use egui::{
-    epaint::{Color32, Mesh, Primitive},
-    vec2, ClippedPrimitive, Pos2, Rect,
+    epaint::{
+        Color32, ColorImage, Mesh, PaintCallbackInfo, Primitive, Vertex, ViewportInPixels, WHITE_UV,
+    },
+    vec2, ClippedPrimitive, Pos2, Rect, TextureFilter, TextureId, TextureOptions, TextureWrapMode,
 };
 use gl::types::{GLchar, GLenum, GLint, GLsizeiptr, GLsync, GLuint};
+use std::collections::VecDeque;
 use std::convert::TryInto;
 use std::ffi::CString;
+use std::time::{Duration, Instant};
 
 const DEFAULT_VERT_SRC: &str = include_str!("../shaders/default.vert");
 const DEFAULT_FRAG_SRC: &str = include_str!("../shaders/default.frag");
 const ADAPTIVE_VERT_SRC: &str = include_str!("../shaders/adaptive.vert");
 const ADAPTIVE_FRAG_SRC: &str = include_str!("../shaders/adaptive.frag");
+const COMPOSITE_VERT_SRC: &str = include_str!("../shaders/composite.vert");
+const COMPOSITE_FRAG_SRC: &str = include_str!("../shaders/composite.frag");
+// Most vertices a batch can have to be drawn with u16 indices.
+const MAX_U16_BATCH_VERTICES: usize = u16::MAX as usize;
+// From GL_EXT_texture_filter_anisotropic, which the generated bindings don't include.
+const TEXTURE_MAX_ANISOTROPY: GLenum = 0x84fe;
+const MAX_TEXTURE_MAX_ANISOTROPY: GLenum = 0x84ff;
+// Multisampled framebuffers kept around, enough for a window, a texture and a few viewports.
+const MAX_MSAA_TARGETS: usize = 4;
+// How long writing a streaming texture waits for the GPU to release a buffer.
+const STREAM_FENCE_TIMEOUT_NS: u64 = 1_000_000_000;
+
+// Timer queries in flight at once. Results usually arrive a frame or two later, so when all of
+// them are still busy a frame simply goes untimed.
+const GPU_TIMER_QUERIES: usize = 4;
 
 #[derive(Default)]
 pub struct Texture {
@@ -28,28 +47,1502 @@ pub struct Texture {
     /// Lazily uploaded
     gl_id: Option<GLuint>,
 
-    /// For user textures there is a choice between
-    /// Linear (default) and Nearest.
-    filtering: bool,
+    /// Sampler state. For user textures there is a choice between
+    /// Linear (default) and Nearest filtering.
+    options: TextureOptions,
 
     /// User textures can be modified and this flag
     /// is used to indicate if pixel data for the
     /// texture has been updated.
     dirty: bool,
+
+    /// Sub-rectangles (position, size) of `pixels` that changed since
+    /// the last upload. Ignored when the whole texture is `dirty`.
+    dirty_regions: Vec<([usize; 2], [usize; 2])>,
+
+    /// Registered from an existing GL texture, so there are no
+    /// `pixels` and the painter never uploads to it.
+    native: bool,
+
+    /// Whether the painter deletes `gl_id` when the texture is freed.
+    ownership: TextureOwnership,
+
+    /// Layout of the pixel data given by the app. `pixels` may be
+    /// stored expanded to RGBA if the context can't use it as is.
+    format: UserTextureFormat,
+
+    /// Anisotropic filtering level of a user texture, 1 or less
+    /// means off.
+    anisotropy: f32,
+
+    /// Pixel buffers of a streaming texture, which has no `pixels`.
+    stream: Option<TextureStream>,
+
+    /// Made with `new_streaming_texture`. Without pixel buffers the
+    /// frames are written to `pixels` instead of a `stream`.
+    streaming: bool,
+}
+
+impl Texture {
+    /// Whether the texture is updated through `pixels`, which native and streaming
+    /// textures aren't.
+    fn has_pixels(&self) -> bool {
+        !self.native && self.stream.is_none()
+    }
+}
+
+/// The pixel buffer objects a streaming texture cycles through, so the app can write the next
+/// frame while the GPU still uploads the previous ones.
+struct TextureStream {
+    buffers: Vec<StreamBuffer>,
+    /// The buffer the next frame is written to.
+    next: usize,
+    /// A buffer written since the last upload.
+    pending: Option<usize>,
+}
+
+struct StreamBuffer {
+    pixel_buffer: GLuint,
+    /// Signaled once the GPU is done uploading from the buffer, null if it never was.
+    fence: GLsync,
+}
+
+impl TextureStream {
+    fn new(buffer_count: usize, size_bytes: usize) -> TextureStream {
+        let buffers = (0..buffer_count.max(1))
+            .map(|_| {
+                let mut pixel_buffer = 0;
+                unsafe {
+                    gl::GenBuffers(1, &mut pixel_buffer);
+                    gl::BindBuffer(gl::PIXEL_UNPACK_BUFFER, pixel_buffer);
+                    gl::BufferData(
+                        gl::PIXEL_UNPACK_BUFFER,
+                        size_bytes as isize,
+                        ptr::null(),
+                        gl::STREAM_DRAW,
+                    );
+                }
+                StreamBuffer {
+                    pixel_buffer,
+                    fence: ptr::null(),
+                }
+            })
+            .collect();
+        unsafe { gl::BindBuffer(gl::PIXEL_UNPACK_BUFFER, 0) };
+
+        TextureStream {
+            buffers,
+            next: 0,
+            pending: None,
+        }
+    }
+
+    /// Maps the next free buffer and lets `write` fill it. Blocks while the GPU still uploads
+    /// from that buffer, which only happens if the app writes frames faster than they are
+    /// painted. Returns false if the buffer could not be mapped.
+    fn write(&mut self, size_bytes: usize, write: impl FnOnce(&mut [u8])) -> bool {
+        let buffer = &mut self.buffers[self.next];
+        unsafe {
+            if !buffer.fence.is_null() {
+                let status = gl::ClientWaitSync(
+                    buffer.fence,
+                    gl::SYNC_FLUSH_COMMANDS_BIT,
+                    STREAM_FENCE_TIMEOUT_NS,
+                );
+                if status == gl::TIMEOUT_EXPIRED || status == gl::WAIT_FAILED {
+                    return false;
+                }
+                gl::DeleteSync(buffer.fence);
+                buffer.fence = ptr::null();
+            }
+
+            gl::BindBuffer(gl::PIXEL_UNPACK_BUFFER, buffer.pixel_buffer);
+            let mapped = gl::MapBufferRange(
+                gl::PIXEL_UNPACK_BUFFER,
+                0,
+                size_bytes as isize,
+                gl::MAP_WRITE_BIT | gl::MAP_INVALIDATE_BUFFER_BIT,
+            );
+            if mapped.is_null() {
+                gl::BindBuffer(gl::PIXEL_UNPACK_BUFFER, 0);
+                return false;
+            }
+            write(std::slice::from_raw_parts_mut(
+                mapped as *mut u8,
+                size_bytes,
+            ));
+            let unmapped = gl::UnmapBuffer(gl::PIXEL_UNPACK_BUFFER) == gl::TRUE;
+            gl::BindBuffer(gl::PIXEL_UNPACK_BUFFER, 0);
+            if !unmapped {
+                // The buffer contents got lost, e.g. on a mode switch.
+                return false;
+            }
+        }
+
+        self.pending = Some(self.next);
+        self.next = (self.next + 1) % self.buffers.len();
+        true
+    }
+
+    /// Uploads the last written buffer, if any, to the texture bound to `TEXTURE_2D` and
+    /// fences it. Returns whether anything was uploaded.
+    fn upload(&mut self, size: (usize, usize), format: &UserTextureFormat) -> bool {
+        let buffer = match self.pending.take() {
+            Some(index) => &mut self.buffers[index],
+            None => return false,
+        };
+        let (_, src_format, src_type) = format.gl_formats(false);
+        unsafe {
+            gl::BindBuffer(gl::PIXEL_UNPACK_BUFFER, buffer.pixel_buffer);
+            gl::TexSubImage2D(
+                gl::TEXTURE_2D,
+                0,
+                0,
+                0,
+                size.0 as i32,
+                size.1 as i32,
+                src_format,
+                src_type,
+                ptr::null(),
+            );
+            gl::BindBuffer(gl::PIXEL_UNPACK_BUFFER, 0);
+            buffer.fence = gl::FenceSync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0);
+        }
+        true
+    }
+
+    fn delete(&self) {
+        for buffer in &self.buffers {
+            unsafe {
+                if !buffer.fence.is_null() {
+                    gl::DeleteSync(buffer.fence);
+                }
+                gl::DeleteBuffers(1, &buffer.pixel_buffer);
+            }
+        }
+    }
+}
+
+/// Who deletes the GL texture behind a `TextureId`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TextureOwnership {
+    /// The painter deletes the GL texture in `free_texture` and `cleanup`.
+    #[default]
+    Owned,
+    /// The app keeps the GL texture alive and deletes it itself, the painter only samples it.
+    Borrowed,
+}
+
+/// The channels stored in each texel of a user texture.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextureChannels {
+    R,
+    Rg,
+    Rgb,
+    Rgba,
+}
+
+impl TextureChannels {
+    fn count(self) -> usize {
+        match self {
+            TextureChannels::R => 1,
+            TextureChannels::Rg => 2,
+            TextureChannels::Rgb => 3,
+            TextureChannels::Rgba => 4,
+        }
+    }
+}
+
+/// How each channel of a user texture is stored, in native byte order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextureComponent {
+    /// 8 bit unsigned, normalized to 0-1.
+    U8,
+    /// 16 bit unsigned, normalized to 0-1. Not available on GLES.
+    U16,
+    /// 16 bit float. Needs GL 3.0 or GLES 3.0.
+    F16,
+    /// 32 bit float. Needs GL 3.0 or GLES 3.0.
+    F32,
+}
+
+impl TextureComponent {
+    fn size(self) -> usize {
+        match self {
+            TextureComponent::U8 => 1,
+            TextureComponent::U16 | TextureComponent::F16 => 2,
+            TextureComponent::F32 => 4,
+        }
+    }
+
+    fn gl_type(self) -> GLenum {
+        match self {
+            TextureComponent::U8 => gl::UNSIGNED_BYTE,
+            TextureComponent::U16 => gl::UNSIGNED_SHORT,
+            TextureComponent::F16 => gl::HALF_FLOAT,
+            TextureComponent::F32 => gl::FLOAT,
+        }
+    }
+
+    /// The bytes of 1.0 in this component type.
+    fn one(self) -> Vec<u8> {
+        match self {
+            TextureComponent::U8 => vec![u8::MAX],
+            TextureComponent::U16 => u16::MAX.to_ne_bytes().to_vec(),
+            TextureComponent::F16 => 0x3c00u16.to_ne_bytes().to_vec(),
+            TextureComponent::F32 => 1.0f32.to_ne_bytes().to_vec(),
+        }
+    }
+}
+
+/// Whether the color channels of a user texture are sRGB encoded, like egui's own colors,
+/// or linear, like heightmaps, masks and HDR images. Alpha is always linear.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextureColorSpace {
+    Srgb,
+    Linear,
+}
+
+/// Where a channel the shader sees comes from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Swizzle {
+    R,
+    G,
+    B,
+    A,
+    Zero,
+    One,
+}
+
+/// Describes the pixel data of a user texture, see [`Painter::new_user_texture_with_format`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UserTextureFormat {
+    pub channels: TextureChannels,
+    pub component: TextureComponent,
+    pub color_space: TextureColorSpace,
+    /// The red, green, blue and alpha the shader sees. Channels missing from the texture
+    /// read as 0, except alpha which reads as 1.
+    pub swizzle: [Swizzle; 4],
+}
+
+impl UserTextureFormat {
+    pub const IDENTITY_SWIZZLE: [Swizzle; 4] = [Swizzle::R, Swizzle::G, Swizzle::B, Swizzle::A];
+
+    /// What `new_user_texture_rgba8` takes, and what egui uses.
+    pub const RGBA8_SRGB: UserTextureFormat = UserTextureFormat {
+        channels: TextureChannels::Rgba,
+        component: TextureComponent::U8,
+        color_space: TextureColorSpace::Srgb,
+        swizzle: Self::IDENTITY_SWIZZLE,
+    };
+
+    /// A single linear channel shown as an opaque grayscale image, e.g. a mask.
+    pub const R8_GRAYSCALE: UserTextureFormat = UserTextureFormat {
+        channels: TextureChannels::R,
+        component: TextureComponent::U8,
+        color_space: TextureColorSpace::Linear,
+        swizzle: [Swizzle::R, Swizzle::R, Swizzle::R, Swizzle::One],
+    };
+
+    /// A single 16 bit float channel shown as grayscale, e.g. a heightmap.
+    pub const R16F_GRAYSCALE: UserTextureFormat = UserTextureFormat {
+        channels: TextureChannels::R,
+        component: TextureComponent::F16,
+        color_space: TextureColorSpace::Linear,
+        swizzle: [Swizzle::R, Swizzle::R, Swizzle::R, Swizzle::One],
+    };
+
+    pub const RG8: UserTextureFormat = UserTextureFormat {
+        channels: TextureChannels::Rg,
+        component: TextureComponent::U8,
+        color_space: TextureColorSpace::Linear,
+        swizzle: Self::IDENTITY_SWIZZLE,
+    };
+
+    /// Linear HDR colors. Values above 1 are clamped by the framebuffer.
+    pub const RGBA16F: UserTextureFormat = UserTextureFormat {
+        channels: TextureChannels::Rgba,
+        component: TextureComponent::F16,
+        color_space: TextureColorSpace::Linear,
+        swizzle: Self::IDENTITY_SWIZZLE,
+    };
+
+    pub fn bytes_per_texel(&self) -> usize {
+        self.channels.count() * self.component.size()
+    }
+
+    /// Internal format, format and type for `TexImage2D`. Without `sized` the internal format
+    /// is the unsized one GLES 2.0 wants.
+    fn gl_formats(&self, sized: bool) -> (GLenum, GLenum, GLenum) {
+        use TextureChannels::*;
+        use TextureComponent::*;
+        let format = match self.channels {
+            R => gl::RED,
+            Rg => gl::RG,
+            Rgb => gl::RGB,
+            Rgba => gl::RGBA,
+        };
+        let internal_format = match (sized, self.channels, self.component) {
+            (false, _, _) => format,
+            (true, R, U8) => gl::R8,
+            (true, R, U16) => gl::R16,
+            (true, R, F16) => gl::R16F,
+            (true, R, F32) => gl::R32F,
+            (true, Rg, U8) => gl::RG8,
+            (true, Rg, U16) => gl::RG16,
+            (true, Rg, F16) => gl::RG16F,
+            (true, Rg, F32) => gl::RG32F,
+            (true, Rgb, U8) => gl::RGB8,
+            (true, Rgb, U16) => gl::RGB16,
+            (true, Rgb, F16) => gl::RGB16F,
+            (true, Rgb, F32) => gl::RGB32F,
+            (true, Rgba, U8) => gl::RGBA8,
+            (true, Rgba, U16) => gl::RGBA16,
+            (true, Rgba, F16) => gl::RGBA16F,
+            (true, Rgba, F32) => gl::RGBA32F,
+        };
+        (internal_format, format, self.component.gl_type())
+    }
+
+    /// The same texels as four channels with the swizzle applied, for contexts that lack
+    /// texture swizzles or one and two channel textures.
+    fn expanded(&self) -> UserTextureFormat {
+        UserTextureFormat {
+            channels: TextureChannels::Rgba,
+            swizzle: Self::IDENTITY_SWIZZLE,
+            ..*self
+        }
+    }
+
+    /// Converts `pixels` from this format to [`UserTextureFormat::expanded`].
+    fn expand(&self, pixels: &[u8]) -> Vec<u8> {
+        let component_size = self.component.size();
+        let texel_size = self.bytes_per_texel();
+        let zero = vec![0; component_size];
+        let one = self.component.one();
+
+        let mut expanded = Vec::with_capacity(pixels.len() / texel_size * component_size * 4);
+        for texel in pixels.chunks_exact(texel_size) {
+            for swizzle in &self.swizzle {
+                let channel = match swizzle {
+                    Swizzle::R => 0,
+                    Swizzle::G => 1,
+                    Swizzle::B => 2,
+                    Swizzle::A => 3,
+                    Swizzle::Zero => {
+                        expanded.extend_from_slice(&zero);
+                        continue;
+                    }
+                    Swizzle::One => {
+                        expanded.extend_from_slice(&one);
+                        continue;
+                    }
+                };
+                if channel < self.channels.count() {
+                    let start = channel * component_size;
+                    expanded.extend_from_slice(&texel[start..start + component_size]);
+                } else if channel == 3 {
+                    expanded.extend_from_slice(&one);
+                } else {
+                    expanded.extend_from_slice(&zero);
+                }
+            }
+        }
+        expanded
+    }
+}
+
+impl Default for UserTextureFormat {
+    fn default() -> Self {
+        Self::RGBA8_SRGB
+    }
+}
+
+/// A callback function that can be used to compose an [`egui::PaintCallback`] for custom
+/// rendering with OpenGL.
+///
+/// The painter sets the GL viewport to [`PaintCallbackInfo::viewport`] and the scissor rect to
+/// [`PaintCallbackInfo::clip_rect`] before invoking the callback. The shader program, vertex
+/// array, blend and texture state used by the painter are restored once the callback returns.
+///
+/// Example:
+///
+/// ```ignore
+/// let callback = egui::PaintCallback {
+///     rect,
+///     callback: std::sync::Arc::new(egui_sdl2_gl::painter::CallbackFn::new(|info, painter| {
+///         // Draw some custom GL stuff here.
+///     })),
+/// };
+/// ui.painter().add(callback);
+/// ```
+pub struct CallbackFn {
+    f: Box<CallbackFnBox>,
+}
+
+type CallbackFnBox = dyn Fn(PaintCallbackInfo, &Painter) + Sync + Send;
+
+impl CallbackFn {
+    pub fn new<F: Fn(PaintCallbackInfo, &Painter) + Sync + Send + 'static>(callback: F) -> Self {
+        let f = Box::new(callback);
+        CallbackFn { f }
+    }
+}
+
+/// An offscreen framebuffer with a single color texture attached, owned by the [`Painter`].
+struct RenderTarget {
+    framebuffer: GLuint,
+    texture: GLuint,
+    size: (u32, u32),
+}
+
+impl RenderTarget {
+    /// A framebuffer with a texture of `internal_format`, either `SRGB8_ALPHA8` or `RGBA8`.
+    fn new(size: (u32, u32), internal_format: GLenum) -> RenderTarget {
+        let (width, height) = size;
+        let mut framebuffer = 0;
+        let mut texture = 0;
+        unsafe {
+            gl::GenTextures(1, &mut texture);
+            gl::BindTexture(gl::TEXTURE_2D, texture);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+            gl::TexImage2D(
+                gl::TEXTURE_2D,
+                0,
+                internal_format as i32,
+                width as i32,
+                height as i32,
+                0,
+                gl::RGBA,
+                gl::UNSIGNED_BYTE,
+                ptr::null(),
+            );
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+
+            gl::GenFramebuffers(1, &mut framebuffer);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, framebuffer);
+            gl::FramebufferTexture2D(
+                gl::FRAMEBUFFER,
+                gl::COLOR_ATTACHMENT0,
+                gl::TEXTURE_2D,
+                texture,
+                0,
+            );
+            debug_assert_eq!(
+                gl::CheckFramebufferStatus(gl::FRAMEBUFFER),
+                gl::FRAMEBUFFER_COMPLETE
+            );
+        }
+
+        RenderTarget {
+            framebuffer,
+            texture,
+            size,
+        }
+    }
+
+    fn delete(&self) {
+        unsafe {
+            gl::DeleteFramebuffers(1, &self.framebuffer);
+            gl::DeleteTextures(1, &self.texture);
+        }
+    }
+}
+
+/// A ring of `GL_TIME_ELAPSED` queries around the egui pass. Results are only read once the
+/// driver says they are available, so the CPU never waits for the GPU.
+struct GpuTimer {
+    // Queries that were ended but whose results haven't been read, oldest first.
+    pending: VecDeque<GLuint>,
+    free: Vec<GLuint>,
+    latest: Option<Duration>,
+}
+
+impl GpuTimer {
+    fn new() -> GpuTimer {
+        let mut free = vec![0; GPU_TIMER_QUERIES];
+        unsafe { gl::GenQueries(free.len() as GLint, free.as_mut_ptr()) };
+        GpuTimer {
+            pending: VecDeque::new(),
+            free,
+            latest: None,
+        }
+    }
+
+    /// Reads the results that are ready, then starts timing unless every query is still busy.
+    /// Returns whether it started, in which case [`GpuTimer::end`] must follow.
+    fn begin(&mut self) -> bool {
+        while let Some(&query) = self.pending.front() {
+            let mut available = 0;
+            unsafe { gl::GetQueryObjectiv(query, gl::QUERY_RESULT_AVAILABLE, &mut available) };
+            if available == 0 {
+                break;
+            }
+            let mut nanoseconds = 0;
+            unsafe { gl::GetQueryObjectui64v(query, gl::QUERY_RESULT, &mut nanoseconds) };
+            self.latest = Some(Duration::from_nanos(nanoseconds));
+            self.pending.pop_front();
+            self.free.push(query);
+        }
+
+        match self.free.pop() {
+            Some(query) => {
+                unsafe { gl::BeginQuery(gl::TIME_ELAPSED, query) };
+                self.pending.push_back(query);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn end(&self) {
+        unsafe { gl::EndQuery(gl::TIME_ELAPSED) };
+    }
+
+    fn delete(&self) {
+        for query in self.pending.iter().chain(&self.free) {
+            unsafe { gl::DeleteQueries(1, query) };
+        }
+    }
+}
+
+/// A multisampled framebuffer egui is painted into, and the texture it is resolved to when it
+/// has to be blended onto the actual target rather than blitted.
+struct MsaaTarget {
+    framebuffer: GLuint,
+    renderbuffer: GLuint,
+    resolve: RenderTarget,
+    samples: u32,
+    // Whether the color buffers are sRGB encoded, like the target they are drawn onto.
+    srgb: bool,
+}
+
+impl MsaaTarget {
+    /// Returns `None` if the driver can't make a complete framebuffer with these settings.
+    fn new(size: (u32, u32), samples: u32, srgb: bool) -> Option<MsaaTarget> {
+        let internal_format = if srgb { gl::SRGB8_ALPHA8 } else { gl::RGBA8 };
+        let mut framebuffer = 0;
+        let mut renderbuffer = 0;
+        let complete = unsafe {
+            gl::GenRenderbuffers(1, &mut renderbuffer);
+            gl::BindRenderbuffer(gl::RENDERBUFFER, renderbuffer);
+            gl::RenderbufferStorageMultisample(
+                gl::RENDERBUFFER,
+                samples as GLint,
+                internal_format,
+                size.0 as GLint,
+                size.1 as GLint,
+            );
+            gl::BindRenderbuffer(gl::RENDERBUFFER, 0);
+
+            gl::GenFramebuffers(1, &mut framebuffer);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, framebuffer);
+            gl::FramebufferRenderbuffer(
+                gl::FRAMEBUFFER,
+                gl::COLOR_ATTACHMENT0,
+                gl::RENDERBUFFER,
+                renderbuffer,
+            );
+            gl::CheckFramebufferStatus(gl::FRAMEBUFFER) == gl::FRAMEBUFFER_COMPLETE
+        };
+        // Binds its own framebuffer, which the caller rebinds anyway.
+        let resolve = RenderTarget::new(size, internal_format);
+
+        let target = MsaaTarget {
+            framebuffer,
+            renderbuffer,
+            resolve,
+            samples,
+            srgb,
+        };
+        if complete {
+            Some(target)
+        } else {
+            target.delete();
+            None
+        }
+    }
+
+    fn delete(&self) {
+        unsafe {
+            gl::DeleteFramebuffers(1, &self.framebuffer);
+            gl::DeleteRenderbuffers(1, &self.renderbuffer);
+        }
+        self.resolve.delete();
+    }
+}
+
+/// Draws a resolved [`MsaaTarget`] onto the target it was painted for, with none of the
+/// dithering, custom shaders or uniforms of the egui pass.
+struct CompositeProgram {
+    program: GLuint,
+    u_sampler: GLint,
+    u_encode_srgb: GLint,
+}
+
+impl CompositeProgram {
+    /// Only for contexts with multisampled framebuffers, so GL 3.0 or GLES 3.0 and up.
+    fn new(caps: &GlCapabilities) -> Result<CompositeProgram, PainterError> {
+        let header = match caps.context {
+            GlContextKind::Es => "#version 300 es\nprecision mediump float;\n",
+            _ if caps.glsl_version >= 150 => "#version 150\n",
+            _ => "#version 130\n",
+        };
+        let program = compile_program(
+            &format!("{}{}", header, COMPOSITE_VERT_SRC),
+            &format!("{}{}", header, COMPOSITE_FRAG_SRC),
+        )?;
+        Ok(CompositeProgram {
+            program,
+            u_sampler: uniform_location(program, "u_sampler"),
+            u_encode_srgb: uniform_location(program, "u_encode_srgb"),
+        })
+    }
+
+    fn delete(&self) {
+        unsafe { gl::DeleteProgram(self.program) };
+    }
+}
+
+/// Attribute and uniform locations of the egui shader program, looked up once.
+struct ProgramLocations {
+    a_pos: GLuint,
+    a_tc: GLuint,
+    a_srgba: GLuint,
+    u_screen_size: GLint,
+    u_sampler: GLint,
+    // Optional, custom shaders without it treat every texture as sRGB.
+    u_texture_linear: GLint,
+    // Optional, custom shaders without it don't dither.
+    u_dither: GLint,
+    // Optional, custom shaders without it only blend in linear space.
+    u_gamma_space: GLint,
+}
+
+impl ProgramLocations {
+    fn new(program: GLuint) -> Result<ProgramLocations, PainterError> {
+        let attrib = |name: &'static str| {
+            let c_name = CString::new(name).unwrap();
+            let loc = unsafe { gl::GetAttribLocation(program, c_name.as_ptr()) };
+            if loc < 0 {
+                return Err(PainterError::MissingShaderInput { name });
+            }
+            Ok(loc as GLuint)
+        };
+        let uniform = |name: &'static str| {
+            let loc = uniform_location(program, name);
+            if loc < 0 {
+                return Err(PainterError::MissingShaderInput { name });
+            }
+            Ok(loc)
+        };
+
+        Ok(ProgramLocations {
+            a_pos: attrib("a_pos")?,
+            a_tc: attrib("a_tc")?,
+            a_srgba: attrib("a_srgba")?,
+            u_screen_size: uniform("u_screen_size")?,
+            u_sampler: uniform("u_sampler")?,
+            u_texture_linear: uniform_location(program, "u_texture_linear"),
+            u_dither: uniform_location(program, "u_dither"),
+            u_gamma_space: uniform_location(program, "u_gamma_space"),
+        })
+    }
+}
+
+fn uniform_location(program: GLuint, name: &str) -> GLint {
+    match CString::new(name) {
+        Ok(c_name) => unsafe { gl::GetUniformLocation(program, c_name.as_ptr()) },
+        Err(_) => -1,
+    }
+}
+
+/// The value of an extra uniform of a custom shader program, see [`Painter::set_uniform`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UniformValue {
+    Int(i32),
+    Float(f32),
+    Vec2([f32; 2]),
+    Vec3([f32; 3]),
+    Vec4([f32; 4]),
+}
+
+impl UniformValue {
+    fn apply(&self, location: GLint) {
+        unsafe {
+            match *self {
+                UniformValue::Int(value) => gl::Uniform1i(location, value),
+                UniformValue::Float(value) => gl::Uniform1f(location, value),
+                UniformValue::Vec2([x, y]) => gl::Uniform2f(location, x, y),
+                UniformValue::Vec3([x, y, z]) => gl::Uniform3f(location, x, y, z),
+                UniformValue::Vec4([x, y, z, w]) => gl::Uniform4f(location, x, y, z, w),
+            }
+        }
+    }
+}
+
+/// A run of indices in the frame's vertex and index buffers that is drawn with a single call.
+/// Indices are relative to `base_vertex`.
+struct MeshBatch {
+    texture_gl_id: GLuint,
+    texture_linear: bool,
+    clip_rect: Rect,
+    first_index: usize,
+    index_count: usize,
+    base_vertex: usize,
+}
+
+enum PaintBatch {
+    Mesh(MeshBatch),
+    Callback(egui::PaintCallback, Rect),
+}
+
+/// What the last [`Painter::paint_jobs`] call did.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FrameStats {
+    /// Meshes painted, before merging.
+    pub meshes: usize,
+    pub draw_calls: usize,
+    pub vertices: usize,
+    pub indices: usize,
+    /// Whole textures and regions of textures uploaded.
+    pub texture_uploads: usize,
+    /// Texture, vertex and index bytes sent to the GPU.
+    pub bytes_uploaded: usize,
+    /// Paint callbacks, including ones for other backends that were skipped.
+    pub callbacks: usize,
+    /// CPU time spent uploading textures, vertices and indices.
+    pub cpu_upload_time: Duration,
+    /// CPU time spent issuing the draw calls, paint callbacks included.
+    pub cpu_draw_time: Duration,
+}
+
+impl FrameStats {
+    fn count_texture_upload(&mut self, bytes: usize) {
+        self.texture_uploads += 1;
+        self.bytes_uploaded += bytes;
+    }
+}
+
+/// Counters for the last frame and totals of what the painter holds, see [`Painter::stats`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PainterStats {
+    pub frame: FrameStats,
+    /// egui, user, native and streaming textures, whether uploaded yet or not.
+    pub textures: usize,
+    /// Estimated GPU memory of the textures, pixel buffers and render target the painter
+    /// owns. Borrowed native textures are not counted.
+    pub texture_bytes: usize,
+    /// Pixel data the painter keeps on the CPU side of its textures.
+    pub cpu_pixel_bytes: usize,
+    /// GPU time of the most recent [`Painter::paint_jobs`] whose timer query has finished,
+    /// usually a frame or two old. `None` before the first result, and on contexts without
+    /// timer queries (GL 3.3 or `GL_ARB_timer_query`). Only one `GL_TIME_ELAPSED` query can
+    /// be active at a time, so don't paint inside one of the app's own.
+    pub gpu_time: Option<Duration>,
+}
+
+/// Debug views of what the painter draws, see [`Painter::debug_modes`]. They are made of
+/// ordinary egui geometry painted in the same pass, so they work with every shader variant.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DebugModes {
+    /// Outlines every triangle in green, on top of everything else but within its clip rect.
+    pub wireframe: bool,
+    /// Outlines the clip rect of every clipped primitive in magenta.
+    pub clip_rects: bool,
+    /// Tints every mesh with a color picked from its `TextureId`.
+    pub tint_textures: bool,
+    /// Replaces the frame with a heat map of how many times each pixel was drawn, going from
+    /// red through yellow to white.
+    pub overdraw: bool,
+}
+
+impl DebugModes {
+    const WIREFRAME_COLOR: Color32 = Color32::from_rgb(0, 255, 0);
+    const CLIP_RECT_COLOR: Color32 = Color32::from_rgb(255, 0, 255);
+    // Additive (zero alpha), so every layer adds to the ones below.
+    const OVERDRAW_COLOR: Color32 = Color32::from_rgba_premultiplied(124, 63, 25, 0);
+
+    /// A color for `id`, with hues spread out so that neighbouring ids look different.
+    fn texture_tint(id: TextureId) -> Color32 {
+        let index = match id {
+            TextureId::Managed(id) => id.wrapping_mul(2),
+            TextureId::User(id) => id.wrapping_mul(2).wrapping_add(1),
+        };
+        let hue = (index as f32 * 0.618_034).fract();
+        egui::ecolor::Hsva::new(hue, 0.75, 1.0, 1.0).into()
+    }
+
+    /// Adds a line from `a` to `b`, `width` points wide, to a mesh of the white texture.
+    fn add_line(mesh: &mut Mesh, a: Pos2, b: Pos2, width: f32, color: Color32) {
+        if a == b {
+            return;
+        }
+        let normal = (b - a).normalized().rot90() * (width / 2.0);
+        let index = mesh.vertices.len() as u32;
+        mesh.colored_vertex(a + normal, color);
+        mesh.colored_vertex(a - normal, color);
+        mesh.colored_vertex(b + normal, color);
+        mesh.colored_vertex(b - normal, color);
+        mesh.add_triangle(index, index + 1, index + 2);
+        mesh.add_triangle(index + 2, index + 1, index + 3);
+    }
+
+    fn add_rect_outline(mesh: &mut Mesh, rect: Rect, width: f32, color: Color32) {
+        // Inset by half the width so the outline is visible at the edges of the screen.
+        let rect = rect.shrink(width / 2.0);
+        let corners = [
+            rect.left_top(),
+            rect.right_top(),
+            rect.right_bottom(),
+            rect.left_bottom(),
+        ];
+        for (i, &corner) in corners.iter().enumerate() {
+            Self::add_line(mesh, corner, corners[(i + 1) % 4], width, color);
+        }
+    }
+
+    fn wireframe(mesh: &Mesh, width: f32) -> Mesh {
+        let mut wireframe = Mesh::default();
+        for triangle in mesh.indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| mesh.vertices[triangle[i] as usize].pos);
+            Self::add_line(&mut wireframe, a, b, width, Self::WIREFRAME_COLOR);
+            Self::add_line(&mut wireframe, b, c, width, Self::WIREFRAME_COLOR);
+            Self::add_line(&mut wireframe, c, a, width, Self::WIREFRAME_COLOR);
+        }
+        wireframe
+    }
+}
+
+/// The color space the painter blends in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ColorMode {
+    /// Picks [`ColorMode::Linear`] if the framebuffer bound when painting is sRGB encoded,
+    /// and [`ColorMode::Gamma`] otherwise.
+    #[default]
+    Auto,
+    /// Blends in linear space, with `GL_FRAMEBUFFER_SRGB` encoding the output. Colors come
+    /// out dark and oversaturated in a framebuffer that isn't sRGB encoded.
+    Linear,
+    /// Keeps all the math in gamma space like egui_glow, which matches egui's reference look
+    /// in a plain RGBA8 framebuffer. Custom shaders need a `uniform bool u_gamma_space` for
+    /// this, in both stages if the vertex shader decodes the vertex colors.
+    Gamma,
+}
+
+/// What the painter does with the GL state it changed once painting is done.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GlStateRestore {
+    /// Leaves the state as painting left it, which is what apps that set up their own state
+    /// every frame expect.
+    #[default]
+    Leave,
+    /// Resets the state to the GL defaults, assuming that is what the app had before painting.
+    /// This needs no queries to the driver, so it is cheap. The clear color is only reset on
+    /// frames the painter cleared.
+    AssumeDefault,
+    /// Snapshots the state before painting and restores it exactly afterwards. Use this when
+    /// egui is painted in the middle of an engine's own rendering.
+    Snapshot,
+}
+
+/// The rectangle of the window egui is painted into, in pixels from the top-left corner of the
+/// window, like SDL mouse coordinates.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SurfaceRect {
+    pub origin: (i32, i32),
+    pub size: (u32, u32),
+}
+
+/// The parts of the GL state the painter touches. Optional parts are the ones the context
+/// may not have.
+struct GlState {
+    framebuffer_srgb: Option<bool>,
+    scissor_test: bool,
+    blend: bool,
+    blend_func: [GLint; 4],
+    active_texture: GLint,
+    texture_2d: GLint,
+    unpack_alignment: GLint,
+    // Row length, skip pixels and skip rows.
+    unpack_subimage: Option<[GLint; 3]>,
+    vertex_array: Option<GLint>,
+    program: GLint,
+    array_buffer: GLint,
+    // Without a vertex array object, the index buffer binding is global state.
+    element_array_buffer: Option<GLint>,
+    viewport: [GLint; 4],
+    scissor_box: [GLint; 4],
+    // `None` leaves the clear color alone.
+    clear_color: Option<[f32; 4]>,
+}
+
+impl GlState {
+    /// The state of a fresh context with a canvas of the given size. The clear color is only
+    /// part of it if painting `cleared`.
+    fn defaults(canvas_size: (u32, u32), caps: &GlCapabilities, cleared: bool) -> GlState {
+        let (width, height) = canvas_size;
+        GlState {
+            framebuffer_srgb: caps.framebuffer_srgb.then_some(false),
+            scissor_test: false,
+            blend: false,
+            blend_func: [
+                gl::ONE as GLint,
+                gl::ZERO as GLint,
+                gl::ONE as GLint,
+                gl::ZERO as GLint,
+            ],
+            active_texture: gl::TEXTURE0 as GLint,
+            texture_2d: 0,
+            unpack_alignment: 4,
+            unpack_subimage: caps.unpack_row_length.then_some([0; 3]),
+            vertex_array: (!caps.legacy).then_some(0),
+            program: 0,
+            array_buffer: 0,
+            element_array_buffer: caps.legacy.then_some(0),
+            viewport: [0, 0, width as GLint, height as GLint],
+            scissor_box: [0, 0, width as GLint, height as GLint],
+            clear_color: cleared.then_some([0.0; 4]),
+        }
+    }
+
+    /// Queries the current state. `texture_unit` is the unit the painter binds its textures to.
+    fn capture(texture_unit: usize, caps: &GlCapabilities) -> GlState {
+        let get = |pname: GLenum| {
+            let mut value = 0;
+            unsafe { gl::GetIntegerv(pname, &mut value) };
+            value
+        };
+        let get4 = |pname: GLenum| {
+            let mut values = [0; 4];
+            unsafe { gl::GetIntegerv(pname, values.as_mut_ptr()) };
+            values
+        };
+
+        unsafe {
+            let active_texture = get(gl::ACTIVE_TEXTURE);
+            gl::ActiveTexture(gl::TEXTURE0 + texture_unit as GLenum);
+            let texture_2d = get(gl::TEXTURE_BINDING_2D);
+            gl::ActiveTexture(active_texture as GLenum);
+
+            let mut clear_color = [0.0; 4];
+            gl::GetFloatv(gl::COLOR_CLEAR_VALUE, clear_color.as_mut_ptr());
+
+            GlState {
+                framebuffer_srgb: caps
+                    .framebuffer_srgb
+                    .then(|| gl::IsEnabled(gl::FRAMEBUFFER_SRGB) == gl::TRUE),
+                scissor_test: gl::IsEnabled(gl::SCISSOR_TEST) == gl::TRUE,
+                blend: gl::IsEnabled(gl::BLEND) == gl::TRUE,
+                blend_func: [
+                    get(gl::BLEND_SRC_RGB),
+                    get(gl::BLEND_DST_RGB),
+                    get(gl::BLEND_SRC_ALPHA),
+                    get(gl::BLEND_DST_ALPHA),
+                ],
+                active_texture,
+                texture_2d,
+                unpack_alignment: get(gl::UNPACK_ALIGNMENT),
+                unpack_subimage: caps.unpack_row_length.then(|| {
+                    [
+                        get(gl::UNPACK_ROW_LENGTH),
+                        get(gl::UNPACK_SKIP_PIXELS),
+                        get(gl::UNPACK_SKIP_ROWS),
+                    ]
+                }),
+                vertex_array: (!caps.legacy).then(|| get(gl::VERTEX_ARRAY_BINDING)),
+                program: get(gl::CURRENT_PROGRAM),
+                array_buffer: get(gl::ARRAY_BUFFER_BINDING),
+                element_array_buffer: caps.legacy.then(|| get(gl::ELEMENT_ARRAY_BUFFER_BINDING)),
+                viewport: get4(gl::VIEWPORT),
+                scissor_box: get4(gl::SCISSOR_BOX),
+                clear_color: Some(clear_color),
+            }
+        }
+    }
+
+    fn restore(&self, texture_unit: usize) {
+        let set_enabled = |cap: GLenum, enabled: bool| unsafe {
+            if enabled {
+                gl::Enable(cap);
+            } else {
+                gl::Disable(cap);
+            }
+        };
+        if let Some(framebuffer_srgb) = self.framebuffer_srgb {
+            set_enabled(gl::FRAMEBUFFER_SRGB, framebuffer_srgb);
+        }
+        set_enabled(gl::SCISSOR_TEST, self.scissor_test);
+        set_enabled(gl::BLEND, self.blend);
+
+        let [src_rgb, dst_rgb, src_alpha, dst_alpha] = self.blend_func;
+        let [x, y, width, height] = self.viewport;
+        let [scissor_x, scissor_y, scissor_width, scissor_height] = self.scissor_box;
+        unsafe {
+            gl::BlendFuncSeparate(
+                src_rgb as GLenum,
+                dst_rgb as GLenum,
+                src_alpha as GLenum,
+                dst_alpha as GLenum,
+            );
+            gl::ActiveTexture(gl::TEXTURE0 + texture_unit as GLenum);
+            gl::BindTexture(gl::TEXTURE_2D, self.texture_2d as GLuint);
+            gl::ActiveTexture(self.active_texture as GLenum);
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, self.unpack_alignment);
+            if let Some([row_length, skip_pixels, skip_rows]) = self.unpack_subimage {
+                gl::PixelStorei(gl::UNPACK_ROW_LENGTH, row_length);
+                gl::PixelStorei(gl::UNPACK_SKIP_PIXELS, skip_pixels);
+                gl::PixelStorei(gl::UNPACK_SKIP_ROWS, skip_rows);
+            }
+            if let Some(vertex_array) = self.vertex_array {
+                gl::BindVertexArray(vertex_array as GLuint);
+            }
+            gl::UseProgram(self.program as GLuint);
+            gl::BindBuffer(gl::ARRAY_BUFFER, self.array_buffer as GLuint);
+            if let Some(element_array_buffer) = self.element_array_buffer {
+                gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, element_array_buffer as GLuint);
+            }
+            gl::Viewport(x, y, width, height);
+            gl::Scissor(scissor_x, scissor_y, scissor_width, scissor_height);
+            if let Some([r, g, b, a]) = self.clear_color {
+                gl::ClearColor(r, g, b, a);
+            }
+        }
+    }
 }
 
 pub struct Painter {
     vertex_array: GLuint,
     program: GLuint,
+    shader_variant: ShaderVariant,
+    caps: GlCapabilities,
+    locations: ProgramLocations,
+    // The built-in program, put aside while a custom one from `set_shader_sources` is in use.
+    builtin_program: Option<(GLuint, ProgramLocations)>,
+    // Extra uniforms of the custom program, with their locations in the current program.
+    uniforms: AHashMap<String, (GLint, UniformValue)>,
     index_buffer: GLuint,
     vertex_buffer: GLuint,
+    // Sizes in bytes of the GPU buffers, which only ever grow.
+    index_buffer_capacity: usize,
+    vertex_buffer_capacity: usize,
+    // CPU side staging of every mesh in a frame, kept to reuse the allocations.
+    indices: Vec<u32>,
+    indices_u16: Vec<u16>,
+    vertices: Vec<Vertex>,
     // Call fence for sdl vsync so the CPU won't heat up if there's no heavy activity.
+    // Null on the legacy path, which has no sync objects.
     pub gl_sync_fence: GLsync,
     textures: AHashMap<egui::TextureId, Texture>,
+    // Ids are never reused, so a freed user texture can't alias a live one.
+    next_user_texture_id: u64,
+    render_target: Option<RenderTarget>,
+    // Requested with `set_msaa_samples`, 0 when painting straight into the target.
+    msaa_samples: u32,
+    // Most recently used first, one per size and encoding of the targets painted onto.
+    msaa_targets: Vec<MsaaTarget>,
+    // Built the first time MSAA is turned on.
+    msaa_composite: Option<CompositeProgram>,
+    dithering: bool,
+    gpu_timer: Option<GpuTimer>,
     pub pixels_per_point: f32,
     pub canvas_size: (u32, u32),
     pub screen_rect: Rect,
+    // Where egui is painted within the canvas, or `None` for the whole canvas.
+    surface: Option<SurfaceRect>,
     pub texture_unit: usize,
+    pub gl_state_restore: GlStateRestore,
+    pub color_mode: ColorMode,
+    /// Debug views painted along with egui, all off by default. Can be changed between
+    /// frames.
+    pub debug_modes: DebugModes,
+    // Whether the current or last frame blends in gamma space, as picked from `color_mode`.
+    gamma_space: bool,
+    // The draw framebuffer last painted into and what it is like, see `target_framebuffer_info`.
+    target_framebuffer: Option<(GLint, FramebufferInfo)>,
+    frame_stats: FrameStats,
+}
+
+/// What the painter needs to know about the framebuffer it paints into.
+#[derive(Clone, Copy)]
+struct FramebufferInfo {
+    srgb: bool,
+    multisampled: bool,
+}
+
+/// The kind of GL context the painter runs in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GlContextKind {
+    Core,
+    Compatibility,
+    Es,
+}
+
+/// The shaders the painter was built with, as picked from its [`ShaderVersion`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ShaderVariant {
+    pub context: GlContextKind,
+    /// The GLSL version the shaders were compiled as, e.g. 150, or 300 for `#version 300 es`.
+    pub glsl_version: u32,
+    /// Whether the adaptive shader sources were used rather than the default GLSL 150 ones.
+    pub adaptive: bool,
+}
+
+impl ShaderVariant {
+    /// Works out the shader variant to use for `shader_ver` in the current context, along with
+    /// the vertex and fragment shader sources.
+    fn select(
+        shader_ver: &ShaderVersion,
+        caps: &GlCapabilities,
+    ) -> (ShaderVariant, String, String) {
+        let (context, glsl_version) = (caps.context, caps.glsl_version);
+        let default = ShaderVariant {
+            context,
+            glsl_version: 150,
+            adaptive: false,
+        };
+        // Without a `#version` line, the adaptive shaders compile as GLSL 1.10 or GLSL ES 1.00.
+        let adaptive = |glsl_version| ShaderVariant {
+            context,
+            glsl_version,
+            adaptive: true,
+        };
+
+        let variant = match shader_ver {
+            ShaderVersion::Default => default,
+            ShaderVersion::Adaptive if context == GlContextKind::Es => adaptive(100),
+            ShaderVersion::Adaptive => adaptive(110),
+            ShaderVersion::Auto => match context {
+                GlContextKind::Es if glsl_version >= 300 => adaptive(300),
+                GlContextKind::Es => adaptive(100),
+                _ if glsl_version >= 150 => default,
+                _ if glsl_version >= 140 => adaptive(140),
+                _ if glsl_version >= 120 => adaptive(120),
+                _ => adaptive(110),
+            },
+        };
+
+        if !variant.adaptive {
+            let (vs_src, fs_src) = (DEFAULT_VERT_SRC.to_string(), DEFAULT_FRAG_SRC.to_string());
+            return (variant, vs_src, fs_src);
+        }
+
+        let mut header = match (shader_ver, context) {
+            (ShaderVersion::Auto, GlContextKind::Es) if variant.glsl_version >= 300 => {
+                "#version 300 es\n".to_string()
+            }
+            (ShaderVersion::Auto, _) => format!("#version {}\n", variant.glsl_version),
+            _ => String::new(),
+        };
+        if !caps.framebuffer_srgb {
+            header.push_str("#define NO_FRAMEBUFFER_SRGB\n");
+        }
+        let (vs_src, fs_src) = (
+            format!("{}{}", header, ADAPTIVE_VERT_SRC),
+            format!("{}{}", header, ADAPTIVE_FRAG_SRC),
+        );
+
+        (variant, vs_src, fs_src)
+    }
+}
+
+/// What the current GL context can do, queried once when the painter is created.
+struct GlCapabilities {
+    context: GlContextKind,
+    /// `GL_SHADING_LANGUAGE_VERSION` times 100, e.g. 150 for GLSL 1.50.
+    glsl_version: u32,
+    /// GL 2.x and GLES 2.0 have no vertex array objects, base vertex draws or sync objects,
+    /// so the painter falls back to binding attributes per draw and u16 indices.
+    legacy: bool,
+    /// Whether `glDrawElementsBaseVertex` is available, which GLES only has from 3.2. Without
+    /// it the attributes are moved to each batch's vertices instead, like on the legacy path.
+    base_vertex: bool,
+    /// Whether `GL_FRAMEBUFFER_SRGB` can be enabled. If not, the shaders gamma-encode
+    /// their output themselves.
+    framebuffer_srgb: bool,
+    /// Whether `GL_UNPACK_ROW_LENGTH` and friends can be used for texture uploads.
+    unpack_row_length: bool,
+    /// Whether one and two channel textures (`GL_RED`, `GL_RG`) are available.
+    texture_rg: bool,
+    /// Whether `GL_TEXTURE_SWIZZLE_*` is available.
+    texture_swizzle: bool,
+    /// Whether `TexImage2D` takes sized internal formats, which GLES 2.0 doesn't.
+    sized_formats: bool,
+    /// Whether `glGenerateMipmap` is available, which GL 2.1 needs an extension for.
+    generate_mipmap: bool,
+    /// Whether textures that aren't a power of two in size can have mipmaps, which GLES 2.0
+    /// needs an extension for.
+    npot_mipmaps: bool,
+    /// The highest anisotropic filtering level, if the context supports it.
+    max_anisotropy: Option<f32>,
+    /// The most samples a multisampled renderbuffer can have, or 0 if the context can't
+    /// resolve one with `glBlitFramebuffer`.
+    max_samples: u32,
+    /// Whether `GL_TIME_ELAPSED` queries are available, which GLES lacks.
+    timer_query: bool,
+}
+
+impl GlCapabilities {
+    fn query() -> GlCapabilities {
+        let version = gl_get_string(gl::VERSION).unwrap_or_default();
+        let context = if version.starts_with("OpenGL ES") {
+            GlContextKind::Es
+        } else {
+            let mut profile_mask = 0;
+            unsafe {
+                // Only GL 3.2+ knows about profiles, older contexts are all compatibility ones.
+                gl::GetIntegerv(gl::CONTEXT_PROFILE_MASK, &mut profile_mask);
+                gl::GetError();
+            }
+            if profile_mask as GLenum & gl::CONTEXT_CORE_PROFILE_BIT != 0 {
+                GlContextKind::Core
+            } else {
+                GlContextKind::Compatibility
+            }
+        };
+        let es = context == GlContextKind::Es;
+
+        // Looks like "4.6.0 NVIDIA 535.0" or "OpenGL ES 3.2 Mesa 23.0".
+        let gl_version = Self::parse_version(&version).unwrap_or(if es { 200 } else { 210 });
+        // Looks like "4.60 NVIDIA" or "OpenGL ES GLSL ES 3.20".
+        let glsl_version = gl_get_string(gl::SHADING_LANGUAGE_VERSION)
+            .and_then(|glsl_version| Self::parse_version(&glsl_version))
+            .unwrap_or(if es { 100 } else { 110 });
+        let extensions = Self::query_extensions(gl_version >= 300);
+
+        let legacy = if es {
+            gl_version < 300
+        } else {
+            gl_version < 320
+        };
+        let base_vertex = !legacy
+            && gl::DrawElementsBaseVertex::is_loaded()
+            && (gl_version >= 320
+                || extensions.contains("GL_OES_draw_elements_base_vertex")
+                || extensions.contains("GL_EXT_draw_elements_base_vertex"));
+        let framebuffer_srgb = !es
+            && (gl_version >= 300
+                || extensions.contains("GL_ARB_framebuffer_sRGB")
+                || extensions.contains("GL_EXT_framebuffer_sRGB"));
+        let unpack_row_length =
+            !es || gl_version >= 300 || extensions.contains("GL_EXT_unpack_subimage");
+        let texture_rg = gl_version >= 300
+            || extensions.contains("GL_ARB_texture_rg")
+            || extensions.contains("GL_EXT_texture_rg");
+        let texture_swizzle = (es && gl_version >= 300)
+            || (!es && gl_version >= 330)
+            || extensions.contains("GL_ARB_texture_swizzle")
+            || extensions.contains("GL_EXT_texture_swizzle");
+
+        let npot_mipmaps = !es || gl_version >= 300 || extensions.contains("GL_OES_texture_npot");
+        let anisotropic = (!es && gl_version >= 460)
+            || extensions.contains("GL_EXT_texture_filter_anisotropic")
+            || extensions.contains("GL_ARB_texture_filter_anisotropic");
+        let max_anisotropy = if anisotropic {
+            let mut max_anisotropy = 0.0;
+            unsafe { gl::GetFloatv(MAX_TEXTURE_MAX_ANISOTROPY, &mut max_anisotropy) };
+            Some(max_anisotropy).filter(|max_anisotropy| *max_anisotropy > 1.0)
+        } else {
+            None
+        };
+
+        let max_samples = if !legacy
+            && gl::BlitFramebuffer::is_loaded()
+            && gl::RenderbufferStorageMultisample::is_loaded()
+        {
+            let mut max_samples = 0;
+            unsafe { gl::GetIntegerv(gl::MAX_SAMPLES, &mut max_samples) };
+            max_samples.max(0) as u32
+        } else {
+            0
+        };
+
+        GlCapabilities {
+            context,
+            glsl_version,
+            legacy,
+            base_vertex,
+            framebuffer_srgb,
+            unpack_row_length,
+            texture_rg,
+            texture_swizzle,
+            sized_formats: !es || gl_version >= 300,
+            generate_mipmap: gl::GenerateMipmap::is_loaded(),
+            npot_mipmaps,
+            max_anisotropy,
+            max_samples,
+            timer_query: !es
+                && (gl_version >= 330 || extensions.contains("GL_ARB_timer_query"))
+                && gl::GetQueryObjectui64v::is_loaded(),
+        }
+    }
+
+    /// `options` without mipmaps if a texture of `size` can't have them in this context, since
+    /// sampling a texture with a missing mip chain gives black.
+    fn texture_options(&self, options: &TextureOptions, size: (usize, usize)) -> TextureOptions {
+        let power_of_two = size.0.is_power_of_two() && size.1.is_power_of_two();
+        if !self.generate_mipmap || (!self.npot_mipmaps && !power_of_two) {
+            TextureOptions {
+                mipmap_mode: None,
+                ..*options
+            }
+        } else {
+            *options
+        }
+    }
+
+    /// Sets the anisotropic filtering of the texture bound to `TEXTURE_2D`, clamped to what
+    /// the context supports. Does nothing without anisotropic filtering.
+    fn apply_anisotropy(&self, anisotropy: f32) {
+        if let Some(max_anisotropy) = self.max_anisotropy {
+            let anisotropy = anisotropy.clamp(1.0, max_anisotropy);
+            unsafe { gl::TexParameterf(gl::TEXTURE_2D, TEXTURE_MAX_ANISOTROPY, anisotropy) };
+        }
+    }
+
+    /// The format `format` is uploaded in. Formats this context can't sample as they are
+    /// get expanded to RGBA on the CPU.
+    fn upload_format(&self, format: &UserTextureFormat) -> UserTextureFormat {
+        let few_channels = format.channels.count() < 3;
+        let swizzled = format.swizzle != UserTextureFormat::IDENTITY_SWIZZLE;
+        if (few_channels && !self.texture_rg) || (swizzled && !self.texture_swizzle) {
+            format.expanded()
+        } else {
+            *format
+        }
+    }
+
+    /// `pixels` in `format`, laid out for [`GlCapabilities::upload_format`].
+    fn upload_pixels(&self, format: &UserTextureFormat, pixels: Vec<u8>) -> Vec<u8> {
+        if self.upload_format(format) == *format {
+            pixels
+        } else {
+            format.expand(&pixels)
+        }
+    }
+
+    /// Finds the first "major.minor" number in `version` and returns it times 100.
+    fn parse_version(version: &str) -> Option<u32> {
+        version.split_whitespace().find_map(|token| {
+            let (major, minor) = token.split_once('.')?;
+            let major: u32 = major.parse().ok()?;
+            let minor: String = minor.chars().take_while(char::is_ascii_digit).collect();
+            if minor.is_empty() {
+                return None;
+            }
+            let minor: u32 = format!("{:0<2}", minor).parse().ok()?;
+            Some(major * 100 + minor)
+        })
+    }
+
+    fn query_extensions(indexed: bool) -> AHashSet<String> {
+        if indexed && gl::GetStringi::is_loaded() {
+            let mut count = 0;
+            unsafe { gl::GetIntegerv(gl::NUM_EXTENSIONS, &mut count) };
+            (0..count.max(0) as GLuint)
+                .filter_map(|i| unsafe {
+                    let name = gl::GetStringi(gl::EXTENSIONS, i);
+                    if name.is_null() {
+                        return None;
+                    }
+                    Some(
+                        std::ffi::CStr::from_ptr(name as *const _)
+                            .to_string_lossy()
+                            .to_string(),
+                    )
+                })
+                .collect()
+        } else {
+            gl_get_string(gl::EXTENSIONS)
+                .unwrap_or_default()
+                .split_whitespace()
+                .map(str::to_string)
+                .collect()
+        }
+    }
+}
+
+/// Why a [`Painter`] could not be created.
+#[derive(Clone, Debug)]
+pub enum PainterError {
+    /// A shader failed to compile.
+    Compile {
+        stage: ShaderStage,
+        info_log: String,
+        gl_version: String,
+    },
+    /// The shader program failed to link.
+    Link {
+        info_log: String,
+        gl_version: String,
+    },
+    /// The GL context lacks something the painter needs.
+    MissingCapability {
+        capability: &'static str,
+        gl_version: String,
+    },
+    /// The shader program lacks an attribute or uniform the painter needs.
+    MissingShaderInput { name: &'static str },
+}
+
+impl core::fmt::Display for PainterError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            PainterError::Compile {
+                stage,
+                info_log,
+                gl_version,
+            } => write!(
+                f,
+                "Failed to compile {} shader (GL {}): {}",
+                stage, gl_version, info_log
+            ),
+            PainterError::Link {
+                info_log,
+                gl_version,
+            } => write!(
+                f,
+                "Failed to link shader program (GL {}): {}",
+                gl_version, info_log
+            ),
+            PainterError::MissingCapability {
+                capability,
+                gl_version,
+            } => write!(f, "GL {} does not support {}", gl_version, capability),
+            PainterError::MissingShaderInput { name } => {
+                write!(f, "Shader program has no active input named {}", name)
+            }
+        }
+    }
+}
+
+impl std::error::Error for PainterError {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShaderStage {
+    Vertex,
+    Fragment,
+}
+
+impl ShaderStage {
+    fn from_gl(ty: GLenum) -> ShaderStage {
+        match ty {
+            gl::VERTEX_SHADER => ShaderStage::Vertex,
+            _ => ShaderStage::Fragment,
+        }
+    }
+}
+
+impl core::fmt::Display for ShaderStage {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            ShaderStage::Vertex => write!(f, "vertex"),
+            ShaderStage::Fragment => write!(f, "fragment"),
+        }
+    }
 }
 
 macro_rules! get_gl_error {
@@ -57,12 +1550,13 @@ macro_rules! get_gl_error {
         let mut len = 0;
         unsafe {
             gl::$fnlen($id, gl::INFO_LOG_LENGTH, &mut len);
-            let mut buf = Vec::with_capacity(len as usize);
+            if len <= 0 {
+                return String::new();
+            }
+            let mut buf = vec![0u8; len as usize];
             gl::$fnlog($id, len, ptr::null_mut(), buf.as_mut_ptr() as *mut GLchar);
-            buf.set_len(len.try_into().unwrap());
-            CString::from_vec_with_nul(buf)
-                .unwrap()
-                .to_string_lossy()
+            String::from_utf8_lossy(&buf)
+                .trim_end_matches('\0')
                 .to_string()
         }
     }};
@@ -76,27 +1570,59 @@ fn get_program_error(id: u32) -> String {
     get_gl_error!(id, GetProgramiv, GetProgramInfoLog)
 }
 
-pub fn compile_shader(src: &str, ty: GLenum) -> GLuint {
-    let shader;
+fn gl_get_string(name: GLenum) -> Option<String> {
+    unsafe {
+        let string = gl::GetString(name);
+        if string.is_null() {
+            return None;
+        }
+        Some(
+            std::ffi::CStr::from_ptr(string as *const _)
+                .to_string_lossy()
+                .to_string(),
+        )
+    }
+}
+
+/// The `GL_VERSION` string of the current context, for error reports.
+pub fn gl_version_string() -> String {
+    gl_get_string(gl::VERSION).unwrap_or_else(|| "unknown".to_string())
+}
+
+pub fn try_compile_shader(src: &str, ty: GLenum) -> Result<GLuint, PainterError> {
+    // Sources can come from the app, see `Painter::set_shader_sources`.
+    let c_str = CString::new(src.as_bytes()).map_err(|err| PainterError::Compile {
+        stage: ShaderStage::from_gl(ty),
+        info_log: format!("the source has a NUL byte at offset {}", err.nul_position()),
+        gl_version: gl_version_string(),
+    })?;
     unsafe {
-        shader = gl::CreateShader(ty);
+        let shader = gl::CreateShader(ty);
         // Attempt to compile the shader
-        let c_str = CString::new(src.as_bytes()).unwrap();
         gl::ShaderSource(shader, 1, &c_str.as_ptr(), ptr::null());
         gl::CompileShader(shader);
         // Get the compile status
         let mut status = gl::FALSE as GLint;
         gl::GetShaderiv(shader, gl::COMPILE_STATUS, &mut status);
 
-        // Fail on error
         if status != (gl::TRUE as GLint) {
-            panic!("{}", get_shader_error(shader));
+            let info_log = get_shader_error(shader);
+            gl::DeleteShader(shader);
+            return Err(PainterError::Compile {
+                stage: ShaderStage::from_gl(ty),
+                info_log,
+                gl_version: gl_version_string(),
+            });
         }
+        Ok(shader)
     }
-    shader
 }
 
-pub fn link_program(vs: GLuint, fs: GLuint) -> GLuint {
+pub fn compile_shader(src: &str, ty: GLenum) -> GLuint {
+    try_compile_shader(src, ty).unwrap_or_else(|err| panic!("{}", err))
+}
+
+pub fn try_link_program(vs: GLuint, fs: GLuint) -> Result<GLuint, PainterError> {
     unsafe {
         let program = gl::CreateProgram();
         gl::AttachShader(program, vs);
@@ -106,67 +1632,401 @@ pub fn link_program(vs: GLuint, fs: GLuint) -> GLuint {
         let mut status = gl::FALSE as GLint;
         gl::GetProgramiv(program, gl::LINK_STATUS, &mut status);
 
-        // Fail on error
         if status != (gl::TRUE as GLint) {
-            panic!("{}", get_program_error(program));
+            let info_log = get_program_error(program);
+            gl::DeleteProgram(program);
+            return Err(PainterError::Link {
+                info_log,
+                gl_version: gl_version_string(),
+            });
+        }
+        Ok(program)
+    }
+}
+
+pub fn link_program(vs: GLuint, fs: GLuint) -> GLuint {
+    try_link_program(vs, fs).unwrap_or_else(|err| panic!("{}", err))
+}
+
+/// Compiles and links a program, cleaning up the shaders either way.
+fn compile_program(vs_src: &str, fs_src: &str) -> Result<GLuint, PainterError> {
+    let vert_shader = try_compile_shader(vs_src, gl::VERTEX_SHADER)?;
+    let frag_shader = match try_compile_shader(fs_src, gl::FRAGMENT_SHADER) {
+        Ok(frag_shader) => frag_shader,
+        Err(err) => {
+            unsafe { gl::DeleteShader(vert_shader) };
+            return Err(err);
+        }
+    };
+
+    let program = try_link_program(vert_shader, frag_shader);
+    unsafe {
+        if let Ok(program) = program {
+            gl::DetachShader(program, vert_shader);
+            gl::DetachShader(program, frag_shader);
+        }
+        gl::DeleteShader(vert_shader);
+        gl::DeleteShader(frag_shader);
+    }
+    program
+}
+
+impl Painter {
+    pub fn new(window: &sdl2::video::Window, scale: f32, shader_ver: ShaderVersion) -> Painter {
+        Self::try_new(window, scale, shader_ver).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Like [`Painter::new`], but returns an error instead of panicking when the GL context
+    /// can't run the painter, so the app can fall back to something else.
+    pub fn try_new(
+        window: &sdl2::video::Window,
+        scale: f32,
+        shader_ver: ShaderVersion,
+    ) -> Result<Painter, PainterError> {
+        gl::load_with(|name| window.subsystem().gl_get_proc_address(name) as *const _);
+        let caps = GlCapabilities::query();
+        Self::check_capabilities(&caps)?;
+
+        unsafe {
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+        }
+        let (shader_variant, vs_src, fs_src) = ShaderVariant::select(&shader_ver, &caps);
+        let (program, locations) = Self::build_program(&vs_src, &fs_src)?;
+
+        let mut vertex_array = 0;
+        let mut index_buffer = 0;
+        let mut vertex_buffer = 0;
+        unsafe {
+            if !caps.legacy {
+                gl::GenVertexArrays(1, &mut vertex_array);
+            }
+            gl::GenBuffers(1, &mut index_buffer);
+            gl::GenBuffers(1, &mut vertex_buffer);
+            if (vertex_array == 0 && !caps.legacy) || index_buffer == 0 || vertex_buffer == 0 {
+                if !caps.legacy {
+                    gl::DeleteVertexArrays(1, &vertex_array);
+                }
+                gl::DeleteBuffers(1, &index_buffer);
+                gl::DeleteBuffers(1, &vertex_buffer);
+                gl::DeleteProgram(program);
+                return Err(PainterError::MissingCapability {
+                    capability: "vertex array and buffer objects",
+                    gl_version: gl_version_string(),
+                });
+            }
+        }
+        if !caps.legacy {
+            Self::setup_vertex_array(vertex_array, vertex_buffer, index_buffer, &locations);
+        }
+        let gpu_timer = caps.timer_query.then(GpuTimer::new);
+        let gl_sync_fence = if caps.legacy {
+            ptr::null()
+        } else {
+            unsafe { gl::FenceSync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0) }
+        };
+
+        let (width, height) = window.size();
+        let pixels_per_point = scale;
+        let rect = vec2(width as f32, height as f32) / pixels_per_point;
+        let screen_rect = Rect::from_min_size(Pos2::new(0f32, 0f32), rect);
+
+        Ok(Painter {
+            vertex_array,
+            program,
+            shader_variant,
+            caps,
+            locations,
+            builtin_program: None,
+            uniforms: AHashMap::default(),
+            index_buffer,
+            vertex_buffer,
+            index_buffer_capacity: 0,
+            vertex_buffer_capacity: 0,
+            indices: Vec::new(),
+            indices_u16: Vec::new(),
+            vertices: Vec::new(),
+            gl_sync_fence,
+            pixels_per_point,
+            textures: Default::default(),
+            next_user_texture_id: 0,
+            render_target: None,
+            msaa_samples: 0,
+            msaa_targets: Vec::new(),
+            msaa_composite: None,
+            dithering: true,
+            gpu_timer,
+            canvas_size: (width, height),
+            screen_rect,
+            surface: None,
+            texture_unit: 0, // Default texture unit
+            gl_state_restore: GlStateRestore::default(),
+            color_mode: ColorMode::default(),
+            debug_modes: DebugModes::default(),
+            gamma_space: false,
+            target_framebuffer: None,
+            frame_stats: FrameStats::default(),
+        })
+    }
+
+    /// Compiles and links a program for the egui pass and looks up its inputs, cleaning up
+    /// after itself on failure.
+    fn build_program(
+        vs_src: &str,
+        fs_src: &str,
+    ) -> Result<(GLuint, ProgramLocations), PainterError> {
+        let program = compile_program(vs_src, fs_src)?;
+        match ProgramLocations::new(program) {
+            Ok(locations) => Ok((program, locations)),
+            Err(err) => {
+                unsafe { gl::DeleteProgram(program) };
+                Err(err)
+            }
+        }
+    }
+
+    /// Checks that the GL functions the painter can't do without were loaded.
+    fn check_capabilities(caps: &GlCapabilities) -> Result<(), PainterError> {
+        let modern = !caps.legacy;
+        let required = [
+            (gl::CreateShader::is_loaded(), "shader programs"),
+            (gl::GenBuffers::is_loaded(), "buffer objects"),
+            (
+                !modern || gl::GenVertexArrays::is_loaded(),
+                "vertex array objects",
+            ),
+            (!modern || gl::FenceSync::is_loaded(), "sync objects"),
+        ];
+
+        match required.iter().find(|(loaded, _)| !loaded) {
+            Some((_, capability)) => Err(PainterError::MissingCapability {
+                capability,
+                gl_version: gl_version_string(),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// The shaders the painter picked, which is mostly of interest with [`ShaderVersion::Auto`].
+    pub fn shader_variant(&self) -> &ShaderVariant {
+        &self.shader_variant
+    }
+
+    /// Replaces the shaders of the egui pass with custom ones, for effects like scanlines or a
+    /// desaturated pause menu. The program must have the attributes `a_pos`, `a_tc` and
+    /// `a_srgba` and the uniforms `u_screen_size` and `u_sampler` of the built-in shaders,
+    /// which are a good starting point. On error the current shaders stay in use.
+    pub fn set_shader_sources(
+        &mut self,
+        vertex_src: &str,
+        fragment_src: &str,
+    ) -> Result<(), PainterError> {
+        let (program, locations) = Self::build_program(vertex_src, fragment_src)?;
+        let previous = self.use_program(program, locations);
+        if self.builtin_program.is_none() {
+            self.builtin_program = Some(previous);
+        } else {
+            unsafe { gl::DeleteProgram(previous.0) };
+        }
+        Ok(())
+    }
+
+    /// Goes back to the built-in shaders after [`Painter::set_shader_sources`].
+    pub fn reset_shader(&mut self) {
+        if let Some((program, locations)) = self.builtin_program.take() {
+            let (custom_program, _) = self.use_program(program, locations);
+            unsafe { gl::DeleteProgram(custom_program) };
+        }
+    }
+
+    /// Sets an extra uniform of a custom shader program. The value is kept and applied every
+    /// frame, so it only needs to be set again when it changes. Names the program doesn't
+    /// have are ignored.
+    pub fn set_uniform(&mut self, name: &str, value: UniformValue) {
+        match self.uniforms.get_mut(name) {
+            Some(uniform) => uniform.1 = value,
+            None => {
+                let location = uniform_location(self.program, name);
+                self.uniforms.insert(name.to_string(), (location, value));
+            }
         }
-        program
     }
-}
 
-impl Painter {
-    pub fn new(window: &sdl2::video::Window, scale: f32, shader_ver: ShaderVersion) -> Painter {
-        unsafe {
-            gl::load_with(|name| window.subsystem().gl_get_proc_address(name) as *const _);
-            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
-            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
-            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
-            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
-            let (vs_src, fs_src) = match shader_ver {
-                ShaderVersion::Default => (DEFAULT_VERT_SRC, DEFAULT_FRAG_SRC),
-                ShaderVersion::Adaptive => (ADAPTIVE_VERT_SRC, ADAPTIVE_FRAG_SRC),
-            };
-            let vs_src = CString::new(vs_src).unwrap().to_string_lossy().to_string();
-            let fs_src = CString::new(fs_src).unwrap().to_string_lossy().to_string();
-            let vert_shader = compile_shader(&vs_src, gl::VERTEX_SHADER);
-            let frag_shader = compile_shader(&fs_src, gl::FRAGMENT_SHADER);
-
-            let program = link_program(vert_shader, frag_shader);
-            let mut vertex_array = 0;
-            let mut index_buffer = 0;
-            let mut vertex_buffer = 0;
-            gl::GenVertexArrays(1, &mut vertex_array);
-            gl::BindVertexArray(vertex_array);
-            assert!(vertex_array > 0);
-            gl::GenBuffers(1, &mut index_buffer);
-            assert!(index_buffer > 0);
-            gl::GenBuffers(1, &mut vertex_buffer);
-            assert!(vert_shader > 0);
+    /// Stops setting an extra uniform, see [`Painter::set_uniform`].
+    pub fn remove_uniform(&mut self, name: &str) {
+        self.uniforms.remove(name);
+    }
+
+    /// Makes `program` the one egui is painted with and returns the previous one.
+    fn use_program(
+        &mut self,
+        program: GLuint,
+        locations: ProgramLocations,
+    ) -> (GLuint, ProgramLocations) {
+        let previous_program = mem::replace(&mut self.program, program);
+        let previous_locations = mem::replace(&mut self.locations, locations);
+        if !self.caps.legacy {
+            unsafe {
+                gl::BindVertexArray(self.vertex_array);
+                gl::DisableVertexAttribArray(previous_locations.a_pos);
+                gl::DisableVertexAttribArray(previous_locations.a_tc);
+                gl::DisableVertexAttribArray(previous_locations.a_srgba);
+            }
+            Self::setup_vertex_array(
+                self.vertex_array,
+                self.vertex_buffer,
+                self.index_buffer,
+                &self.locations,
+            );
+            unsafe { gl::BindVertexArray(0) };
+        }
+        for (name, (location, _)) in self.uniforms.iter_mut() {
+            *location = uniform_location(program, name);
+        }
+        (previous_program, previous_locations)
+    }
+
+    /// Counters for the last [`Painter::paint_jobs`] call, and totals of the textures the
+    /// painter holds, e.g. to catch texture leaks.
+    pub fn stats(&self) -> PainterStats {
+        let mut stats = PainterStats {
+            frame: self.frame_stats,
+            textures: self.textures.len(),
+            gpu_time: self.gpu_timer.as_ref().and_then(|timer| timer.latest),
+            ..Default::default()
+        };
+
+        for texture in self.textures.values() {
+            stats.cpu_pixel_bytes += texture.pixels.capacity();
+            if texture.gl_id.is_none() || texture.ownership == TextureOwnership::Borrowed {
+                continue;
+            }
+            let (width, height) = texture.size;
+            let texel_size = if texture.native {
+                // Native textures are opaque to us, assume rgba8.
+                4
+            } else {
+                self.caps.upload_format(&texture.format).bytes_per_texel()
+            };
+            let mut bytes = width * height * texel_size;
+            if self
+                .caps
+                .texture_options(&texture.options, texture.size)
+                .mipmap_mode
+                .is_some()
+            {
+                // A full mip chain adds a third.
+                bytes += bytes / 3;
+            }
+            if let Some(stream) = &texture.stream {
+                bytes += stream.buffers.len() * width * height * texel_size;
+            }
+            stats.texture_bytes += bytes;
+        }
+
+        if let Some(target) = &self.render_target {
+            stats.texture_bytes += target.size.0 as usize * target.size.1 as usize * 4;
+        }
+        for msaa in &self.msaa_targets {
+            let (width, height) = msaa.resolve.size;
+            stats.texture_bytes +=
+                width as usize * height as usize * 4 * (msaa.samples as usize + 1);
+        }
+        stats
+    }
+
+    /// Whether the painter runs without vertex array objects and with u16 indices, as it does
+    /// on GL 2.x and GLES 2.0 contexts.
+    pub fn uses_legacy_path(&self) -> bool {
+        self.caps.legacy
+    }
+
+    /// Paints egui into an offscreen framebuffer with `samples` samples per pixel, and resolves
+    /// it onto the target afterwards. This smooths the edges of user texture quads and paint
+    /// callbacks, however the window was created. 0 or 1 paints straight into the target again.
+    ///
+    /// The count is clamped to what the context supports, and the count in use is returned. It
+    /// is 0 on contexts without multisampled framebuffers, which includes the legacy path.
+    /// Paint callbacks draw into the offscreen framebuffer, so a callback that binds a
+    /// framebuffer of its own should rebind the one that was bound when it was called.
+    pub fn set_msaa_samples(&mut self, samples: u32) -> u32 {
+        self.msaa_samples = samples.min(self.caps.max_samples);
+        if self.msaa_samples < 2 {
+            self.msaa_samples = 0;
+        } else if self.msaa_composite.is_none() {
+            match CompositeProgram::new(&self.caps) {
+                Ok(composite) => self.msaa_composite = Some(composite),
+                // Without it a frame could only be resolved over an opaque background.
+                Err(_) => self.msaa_samples = 0,
+            }
+        }
+        let samples = self.msaa_samples;
+        self.msaa_targets.retain(|msaa| {
+            if msaa.samples != samples {
+                msaa.delete();
+            }
+            msaa.samples == samples
+        });
+        self.msaa_samples
+    }
+
+    /// The samples per pixel set with [`Painter::set_msaa_samples`], 0 if egui is painted
+    /// straight into the target.
+    pub fn msaa_samples(&self) -> u32 {
+        self.msaa_samples
+    }
+
+    /// Whether the current or last frame was painted in gamma space, as picked from
+    /// [`Painter::color_mode`].
+    pub fn blends_in_gamma_space(&self) -> bool {
+        self.gamma_space
+    }
 
-            let (width, height) = window.size();
-            let pixels_per_point = scale;
-            let rect = vec2(width as f32, height as f32) / pixels_per_point;
-            let screen_rect = Rect::from_min_size(Pos2::new(0f32, 0f32), rect);
+    /// Works out whether to paint in gamma space with the framebuffer about to be painted into.
+    fn pick_gamma_space(&mut self) -> bool {
+        match self.color_mode {
+            ColorMode::Linear => false,
+            ColorMode::Gamma => true,
+            ColorMode::Auto => !self.target_framebuffer_info().srgb,
+        }
+    }
 
-            gl::DetachShader(program, vert_shader);
-            gl::DetachShader(program, frag_shader);
-            gl::DeleteShader(vert_shader);
-            gl::DeleteShader(frag_shader);
-            Painter {
-                vertex_array,
-                program,
-                index_buffer,
-                vertex_buffer,
-                gl_sync_fence: gl::FenceSync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0),
-                pixels_per_point,
-                textures: Default::default(),
-                canvas_size: (width, height),
-                screen_rect,
-                texture_unit: 0, // Default texture unit
+    /// Describes the bound draw framebuffer.
+    fn target_framebuffer_info(&mut self) -> FramebufferInfo {
+        let mut framebuffer = 0;
+        unsafe { gl::GetIntegerv(gl::DRAW_FRAMEBUFFER_BINDING, &mut framebuffer) };
+        match self.target_framebuffer {
+            // The queries stall some drivers, so they are only made for new framebuffers.
+            Some((last_framebuffer, info)) if last_framebuffer == framebuffer => info,
+            _ => {
+                let mut sample_buffers = 0;
+                unsafe { gl::GetIntegerv(gl::SAMPLE_BUFFERS, &mut sample_buffers) };
+                let info = FramebufferInfo {
+                    srgb: Self::framebuffer_is_srgb(gl::DRAW_FRAMEBUFFER),
+                    multisampled: sample_buffers > 0,
+                };
+                self.target_framebuffer = Some((framebuffer, info));
+                info
             }
         }
     }
 
+    /// Turns dithering of the output on or off. It is on by default, like in egui's own
+    /// painters, and hides banding in gradients and shadows on 8 bit framebuffers.
+    ///
+    /// The noise is added to the gamma encoded colors, so it looks the same whether the
+    /// framebuffer blends in linear space or the shaders encode the output themselves. Custom
+    /// shaders dither if they declare a `uniform bool u_dither`.
+    pub fn set_dithering(&mut self, dithering: bool) {
+        self.dithering = dithering;
+    }
+
+    pub fn dithering(&self) -> bool {
+        self.dithering
+    }
+
     pub fn get_texture(&self, id: &egui::TextureId) -> Option<&Texture> {
         self.textures.get(id)
     }
@@ -175,25 +2035,144 @@ impl Painter {
         self.textures.get(id)?.gl_id
     }
 
+    /// Points an existing texture at `gl_tex_id`, which stays owned by the app. Prefer
+    /// [`Painter::register_native_texture`], which doesn't need a texture to start from.
     pub fn set_raw_gl_texture_id(
         &mut self,
         egui_tex_id: &egui::TextureId,
         gl_tex_id: GLuint,
     ) -> bool {
         if let Some(texture) = self.textures.get_mut(egui_tex_id) {
+            Self::delete_gl_objects(texture);
+            texture.stream = None;
             texture.gl_id = Some(gl_tex_id);
+            texture.pixels = Vec::new();
+            texture.dirty = false;
+            texture.dirty_regions.clear();
+            texture.native = true;
+            texture.ownership = TextureOwnership::Borrowed;
             return true;
         }
         false
     }
 
+    /// Makes an existing GL texture, e.g. one an engine rendered into, available to egui.
+    /// `size` is in texels and `options` are applied to the texture's sampling parameters.
+    /// With [`TextureOwnership::Owned`] the painter deletes the texture once the id is freed,
+    /// with [`TextureOwnership::Borrowed`] it is left for the app to delete.
+    pub fn register_native_texture(
+        &mut self,
+        gl_id: GLuint,
+        size: (usize, usize),
+        options: TextureOptions,
+        ownership: TextureOwnership,
+    ) -> egui::TextureId {
+        let id = self.alloc_user_texture_id();
+        self.textures
+            .insert(id, Self::native_texture(gl_id, size, options, ownership));
+        id
+    }
+
+    /// Swaps the GL texture behind a texture registered with
+    /// [`Painter::register_native_texture`], e.g. after the engine recreated it at a new size.
+    /// The previous texture is deleted if the painter owned it. Returns false if `id` is not a
+    /// native texture.
+    pub fn replace_native_texture(
+        &mut self,
+        id: egui::TextureId,
+        gl_id: GLuint,
+        size: (usize, usize),
+        options: TextureOptions,
+        ownership: TextureOwnership,
+    ) -> bool {
+        match self.textures.get_mut(&id) {
+            Some(texture) if texture.native => {
+                if texture.gl_id != Some(gl_id) {
+                    Self::delete_gl_objects(texture);
+                }
+                *texture = Self::native_texture(gl_id, size, options, ownership);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn native_texture(
+        gl_id: GLuint,
+        size: (usize, usize),
+        options: TextureOptions,
+        ownership: TextureOwnership,
+    ) -> Texture {
+        Self::with_texture_bound(gl_id, || Self::apply_texture_options(&options));
+        Texture {
+            size,
+            gl_id: Some(gl_id),
+            options,
+            native: true,
+            ownership,
+            ..Default::default()
+        }
+    }
+
+    /// Deletes the GL texture if the painter owns it, and the pixel buffers of a streaming
+    /// texture.
+    fn delete_gl_objects(texture: &Texture) {
+        if let (Some(texture_gl_id), TextureOwnership::Owned) = (texture.gl_id, texture.ownership) {
+            unsafe { gl::DeleteTextures(1, &texture_gl_id) }
+        }
+        if let Some(stream) = &texture.stream {
+            stream.delete();
+        }
+    }
+
+    /// An rgba8 texture whose pixels can be updated, which native and streaming textures
+    /// can't. Textures in other formats are updated with [`Painter::update_user_texture_bytes`].
+    fn user_texture_rgba8_mut(&mut self, id: egui::TextureId) -> Option<&mut Texture> {
+        self.textures.get_mut(&id).filter(|texture| {
+            texture.has_pixels() && texture.format == UserTextureFormat::RGBA8_SRGB
+        })
+    }
+
     pub fn update_screen_rect(&mut self, size: (u32, u32)) {
         self.canvas_size = size;
-        let (x, y) = size;
+        let (x, y) = self.surface_rect().size;
         let rect = vec2(x as f32, y as f32) / self.pixels_per_point;
         self.screen_rect = Rect::from_min_size(Default::default(), rect);
     }
 
+    /// Paints egui into `surface` instead of the whole canvas, e.g. a panel docked next to
+    /// the app's own 3D view. `None` goes back to the whole canvas.
+    ///
+    /// Painting, clip rects, paint callbacks and screenshots are all confined to the surface,
+    /// and [`crate::input_to_egui`] moves pointer positions so that the top-left corner of
+    /// the surface is egui's origin. [`Painter::screen_rect`] becomes the size of the surface,
+    /// so copy it to the `screen_rect` of the egui input as well.
+    pub fn set_surface(&mut self, surface: Option<SurfaceRect>) {
+        self.surface = surface;
+        self.update_screen_rect(self.canvas_size);
+    }
+
+    /// The rectangle set with [`Painter::set_surface`], if any.
+    pub fn surface(&self) -> Option<SurfaceRect> {
+        self.surface
+    }
+
+    /// The rectangle egui is painted into, which is the whole canvas without a surface.
+    pub fn surface_rect(&self) -> SurfaceRect {
+        self.surface.unwrap_or(SurfaceRect {
+            origin: (0, 0),
+            size: self.canvas_size,
+        })
+    }
+
+    /// The surface as a GL viewport: x, y from the bottom of the canvas, width and height.
+    fn surface_viewport(&self) -> [GLint; 4] {
+        let surface = self.surface_rect();
+        let (width, height) = (surface.size.0 as GLint, surface.size.1 as GLint);
+        let bottom = self.canvas_size.1 as GLint - surface.origin.1 - height;
+        [surface.origin.0, bottom, width, height]
+    }
+
     pub fn new_user_texture(
         &mut self,
         size: (usize, usize),
@@ -210,15 +2189,16 @@ impl Painter {
             pixels.push(srgba[3]);
         }
 
-        let id = egui::TextureId::User(self.textures.len() as u64);
+        let id = self.alloc_user_texture_id();
         self.textures.insert(
             id,
             Texture {
                 size,
                 pixels,
                 gl_id: None,
-                filtering,
+                options: Self::user_texture_options(filtering),
                 dirty: true,
+                ..Default::default()
             },
         );
 
@@ -232,35 +2212,166 @@ impl Painter {
         rgba8_pixels: Vec<u8>,
         filtering: bool,
     ) -> egui::TextureId {
-        let id = egui::TextureId::User(self.textures.len() as u64);
+        let id = self.alloc_user_texture_id();
         self.textures.insert(
             id,
             Texture {
                 size,
                 pixels: rgba8_pixels,
                 gl_id: None,
-                filtering,
+                options: Self::user_texture_options(filtering),
+                dirty: true,
+                ..Default::default()
+            },
+        );
+
+        id
+    }
+
+    fn alloc_user_texture_id(&mut self) -> egui::TextureId {
+        let id = egui::TextureId::User(self.next_user_texture_id);
+        self.next_user_texture_id += 1;
+        id
+    }
+
+    /// Creates a user texture from `pixels` in `format`, e.g. a single channel mask or a float
+    /// HDR image, without converting it to rgba8 first. Formats the context can't sample
+    /// directly are expanded to RGBA when uploading.
+    pub fn new_user_texture_with_format(
+        &mut self,
+        size: (usize, usize),
+        pixels: Vec<u8>,
+        format: UserTextureFormat,
+        options: TextureOptions,
+    ) -> egui::TextureId {
+        assert_eq!(size.0 * size.1 * format.bytes_per_texel(), pixels.len());
+
+        let id = self.alloc_user_texture_id();
+        self.textures.insert(
+            id,
+            Texture {
+                size,
+                pixels: self.caps.upload_pixels(&format, pixels),
+                gl_id: None,
+                options,
                 dirty: true,
+                format,
+                ..Default::default()
             },
         );
 
         id
     }
 
+    /// Creates a texture for content that changes every frame, like video. Frames are written
+    /// to a ring of `buffer_count` pixel buffer objects (2 or 3 is typical) and uploaded from
+    /// there asynchronously, so writing a frame doesn't wait for the previous upload. On
+    /// contexts without pixel buffer and sync objects it is an ordinary rgba8 user texture.
+    /// Write frames with [`Painter::write_streaming_texture`].
+    pub fn new_streaming_texture(
+        &mut self,
+        size: (usize, usize),
+        options: TextureOptions,
+        buffer_count: usize,
+    ) -> egui::TextureId {
+        let format = UserTextureFormat::RGBA8_SRGB;
+        let size_bytes = size.0 * size.1 * format.bytes_per_texel();
+        let id = self.alloc_user_texture_id();
+        if self.caps.legacy {
+            self.textures.insert(
+                id,
+                Texture {
+                    size,
+                    pixels: vec![0; size_bytes],
+                    options,
+                    dirty: true,
+                    streaming: true,
+                    ..Default::default()
+                },
+            );
+            return id;
+        }
+
+        // Allocate the storage now, frames only ever replace its contents.
+        let mut gl_id = None;
+        let pixels = vec![0; size_bytes];
+        // Uploading binds the new texture, so restore the app's binding afterwards.
+        Self::with_texture_bound(0, || {
+            Self::use_gl_texture2d(
+                &mut gl_id,
+                &pixels,
+                size.0 as i32,
+                size.1 as i32,
+                &options,
+                &format,
+                &self.caps,
+            )
+        });
+
+        self.textures.insert(
+            id,
+            Texture {
+                size,
+                gl_id,
+                options,
+                stream: Some(TextureStream::new(buffer_count, size_bytes)),
+                streaming: true,
+                ..Default::default()
+            },
+        );
+        id
+    }
+
+    /// Writes the next frame of a streaming texture. `write` gets the whole frame's rgba8
+    /// pixels to fill, which is mapped GPU memory, so decoders can write into it directly.
+    /// The frame is uploaded the next time egui is painted. Returns false if `id` is not a
+    /// streaming texture, or the frame could not be written.
+    pub fn write_streaming_texture(
+        &mut self,
+        id: egui::TextureId,
+        write: impl FnOnce(&mut [u8]),
+    ) -> bool {
+        let texture = match self.textures.get_mut(&id) {
+            Some(texture) if texture.streaming => texture,
+            _ => return false,
+        };
+        let (width, height) = texture.size;
+        let size_bytes = width * height * texture.format.bytes_per_texel();
+        match &mut texture.stream {
+            Some(stream) => stream.write(size_bytes, write),
+            // The fallback for contexts without pixel buffers.
+            None => {
+                write(&mut texture.pixels);
+                texture.dirty = true;
+                true
+            }
+        }
+    }
+
+    /// Copies `rgba8_pixels` into the next frame of a streaming texture, see
+    /// [`Painter::write_streaming_texture`].
+    pub fn update_streaming_texture(&mut self, id: egui::TextureId, rgba8_pixels: &[u8]) -> bool {
+        self.write_streaming_texture(id, |frame| frame.copy_from_slice(rgba8_pixels))
+    }
+
     /// fn free_texture() and fn free() implemented from epi both are basically the same.
+    /// Borrowed native textures are only forgotten, not deleted.
     pub fn free_texture(&mut self, id: egui::TextureId) {
-        if let Some(Texture {
-            gl_id: Some(texture_id),
-            ..
-        }) = self.textures.get(&id)
-        {
-            unsafe { gl::DeleteTextures(1, texture_id) }
-            self.textures.remove(&id);
+        if let Some(texture) = self.textures.remove(&id) {
+            Self::delete_gl_objects(&texture);
         }
     }
 
+    /// Replaces all pixels of an rgba8 user texture. Textures in other formats are left alone.
     pub fn update_user_texture_data(&mut self, id: egui::TextureId, _pixels: &[Color32]) {
-        if let Some(Texture { pixels, dirty, .. }) = self.textures.get_mut(&id) {
+        if let Some(Texture {
+            size,
+            pixels,
+            dirty,
+            ..
+        }) = self.user_texture_rgba8_mut(id)
+        {
+            assert_eq!(size.0 * size.1, _pixels.len());
             *pixels = Vec::with_capacity(pixels.len() * 4);
 
             for p in _pixels {
@@ -274,23 +2385,146 @@ impl Painter {
         }
     }
 
-    /// Updates texture rgba8 data
+    /// Updates texture rgba8 data. Textures in other formats are left alone.
     pub fn update_user_texture_rgba8_data(&mut self, id: egui::TextureId, rgba8_pixels: Vec<u8>) {
-        if let Some(Texture { pixels, dirty, .. }) = self.textures.get_mut(&id) {
+        if let Some(Texture {
+            size,
+            pixels,
+            dirty,
+            ..
+        }) = self.user_texture_rgba8_mut(id)
+        {
+            assert_eq!(size.0 * size.1 * 4, rgba8_pixels.len());
             *pixels = rgba8_pixels;
             *dirty = true
         };
     }
 
+    /// Changes how a user texture is sampled. Turning on mipmaps makes the painter generate
+    /// them now and again after every update. Contexts that can't generate mipmaps for the
+    /// texture sample it without them.
+    pub fn set_user_texture_options(&mut self, id: egui::TextureId, options: TextureOptions) {
+        let caps = &self.caps;
+        if let Some(texture) = self.textures.get_mut(&id).filter(|texture| !texture.native) {
+            texture.options = options;
+            if let Some(gl_id) = texture.gl_id {
+                let options = caps.texture_options(&options, texture.size);
+                Self::with_texture_bound(gl_id, || {
+                    Self::apply_texture_options(&options);
+                    if options.mipmap_mode.is_some() {
+                        unsafe { gl::GenerateMipmap(gl::TEXTURE_2D) };
+                    }
+                });
+            }
+        }
+    }
+
+    /// Sets the anisotropic filtering level of a user texture, which keeps textures shown at
+    /// an angle or squashed sharp. It is clamped to [`Painter::max_anisotropy`] and ignored
+    /// when the context has no anisotropic filtering.
+    pub fn set_user_texture_anisotropy(&mut self, id: egui::TextureId, anisotropy: f32) {
+        let caps = &self.caps;
+        if let Some(texture) = self.textures.get_mut(&id).filter(|texture| !texture.native) {
+            texture.anisotropy = anisotropy;
+            if let Some(gl_id) = texture.gl_id {
+                Self::with_texture_bound(gl_id, || caps.apply_anisotropy(anisotropy));
+            }
+        }
+    }
+
+    /// The highest anisotropic filtering level, or `None` if the context lacks
+    /// `GL_EXT_texture_filter_anisotropic`.
+    pub fn max_anisotropy(&self) -> Option<f32> {
+        self.caps.max_anisotropy
+    }
+
+    /// Replaces all pixels of a user texture with `pixels` in the texture's format.
+    pub fn update_user_texture_bytes(&mut self, id: egui::TextureId, pixels: Vec<u8>) {
+        let caps = &self.caps;
+        if let Some(texture) = self
+            .textures
+            .get_mut(&id)
+            .filter(|texture| texture.has_pixels())
+        {
+            let (width, height) = texture.size;
+            assert_eq!(
+                width * height * texture.format.bytes_per_texel(),
+                pixels.len()
+            );
+            texture.pixels = caps.upload_pixels(&texture.format, pixels);
+            texture.dirty = true;
+        }
+    }
+
+    /// Updates a sub-rectangle of a user texture with data in its format, rgba8 unless it was
+    /// created with [`Painter::new_user_texture_with_format`]. `pos` and `size` are in
+    /// texels and `pixels` holds `size[0] * size[1]` tightly packed rows. Only the changed
+    /// region is uploaded, which is much cheaper than replacing a large texture.
+    pub fn update_user_texture_region(
+        &mut self,
+        id: egui::TextureId,
+        pos: [usize; 2],
+        size: [usize; 2],
+        pixels: &[u8],
+    ) {
+        let caps = &self.caps;
+        if let Some(texture) = self
+            .textures
+            .get_mut(&id)
+            .filter(|texture| texture.has_pixels())
+        {
+            let format = texture.format;
+            assert_eq!(size[0] * size[1] * format.bytes_per_texel(), pixels.len());
+            let (width, height) = texture.size;
+            assert!(
+                pos[0] + size[0] <= width && pos[1] + size[1] <= height,
+                "Region is outside of the texture"
+            );
+            if size[0] == 0 || size[1] == 0 {
+                return;
+            }
+
+            let pixels = caps.upload_pixels(&format, pixels.to_vec());
+            let texel_size = caps.upload_format(&format).bytes_per_texel();
+            let row_bytes = size[0] * texel_size;
+            for (row, src) in pixels.chunks_exact(row_bytes).enumerate() {
+                let start = ((pos[1] + row) * width + pos[0]) * texel_size;
+                texture.pixels[start..start + row_bytes].copy_from_slice(src);
+            }
+
+            if !texture.dirty {
+                texture.dirty_regions.push((pos, size));
+            }
+        }
+    }
+
     pub fn paint_jobs(
         &mut self,
         bg_color: Option<Color32>,
         textures_delta: egui::TexturesDelta,
         primitives: Vec<ClippedPrimitive>,
     ) {
+        let saved_state = match self.gl_state_restore {
+            GlStateRestore::Leave | GlStateRestore::AssumeDefault => None,
+            GlStateRestore::Snapshot => Some(GlState::capture(self.texture_unit, &self.caps)),
+        };
+        self.frame_stats = FrameStats::default();
+        self.gamma_space = self.pick_gamma_space();
+        // The overdraw heat map starts from black.
+        let bg_color = if self.debug_modes.overdraw {
+            Some(Color32::BLACK)
+        } else {
+            bg_color
+        };
+        let timing_gpu = self.gpu_timer.as_mut().is_some_and(GpuTimer::begin);
+        let upload_start = Instant::now();
+
         unsafe {
-            gl::PixelStorei(gl::UNPACK_ROW_LENGTH, 0);
-            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 4);
+            if self.caps.unpack_row_length {
+                gl::PixelStorei(gl::UNPACK_ROW_LENGTH, 0);
+            }
+            // Rows of one and three channel textures aren't always 4 byte aligned.
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
             gl::ActiveTexture(gl::TEXTURE0 + self.texture_unit as GLenum);
         }
 
@@ -299,11 +2533,19 @@ impl Painter {
         }
 
         self.upload_user_textures();
+        let mut upload_time = upload_start.elapsed();
+        let draw_start = Instant::now();
+
+        let msaa_resolve_target = self.begin_msaa();
 
-        let (canvas_width, canvas_height) = self.canvas_size;
-        let pixels_per_point = self.pixels_per_point;
         unsafe {
             if let Some(color) = bg_color {
+                if self.surface.is_some() {
+                    // Only clear our part of the window.
+                    let [x, y, width, height] = self.surface_viewport();
+                    gl::Enable(gl::SCISSOR_TEST);
+                    gl::Scissor(x, y, width, height);
+                }
                 gl::ClearColor(
                     color[0] as f32 / 255.0,
                     color[1] as f32 / 255.0,
@@ -313,99 +2555,484 @@ impl Painter {
 
                 gl::Clear(gl::COLOR_BUFFER_BIT);
             }
+        }
+
+        self.prepare_painting();
+        let primitives_start = Instant::now();
+        let batches = self.upload_primitives(primitives);
+        let primitives_time = primitives_start.elapsed();
+        upload_time += primitives_time;
+
+        for batch in batches {
+            match batch {
+                PaintBatch::Mesh(batch) => {
+                    self.paint_batch(&batch);
+                    self.frame_stats.draw_calls += 1;
+                }
+                PaintBatch::Callback(callback, clip_rect) => {
+                    self.paint_callback(&callback, clip_rect);
+                    self.frame_stats.callbacks += 1;
+                }
+            }
+        }
+
+        if let Some(framebuffers) = msaa_resolve_target {
+            self.resolve_msaa(framebuffers, bg_color.is_some());
+        }
+
+        if self.caps.legacy {
+            // Without a vertex array object our attributes are global state.
+            unsafe {
+                gl::DisableVertexAttribArray(self.locations.a_pos);
+                gl::DisableVertexAttribArray(self.locations.a_tc);
+                gl::DisableVertexAttribArray(self.locations.a_srgba);
+            }
+        }
+        // Only clearing changes the clear color, and MSAA always clears.
+        let cleared = bg_color.is_some() || msaa_resolve_target.is_some();
+        let restore_state = saved_state.or_else(|| {
+            (self.gl_state_restore == GlStateRestore::AssumeDefault)
+                .then(|| GlState::defaults(self.canvas_size, &self.caps, cleared))
+        });
+        if let Some(state) = restore_state {
+            state.restore(self.texture_unit);
+        }
+
+        if timing_gpu {
+            if let Some(timer) = &self.gpu_timer {
+                timer.end();
+            }
+        }
+        self.frame_stats.cpu_upload_time = upload_time;
+        self.frame_stats.cpu_draw_time = draw_start.elapsed().saturating_sub(primitives_time);
+
+        for texture_id in textures_delta.free {
+            self.free_texture(texture_id);
+        }
+    }
+
+    /// Sets up the GL state needed to paint egui meshes. This is called at the start of
+    /// painting and again after every paint callback, since callbacks may change any of it.
+    fn prepare_painting(&self) {
+        let viewport = self.surface_viewport();
+        unsafe {
             //Let OpenGL know we are dealing with SRGB colors so that it
             //can do the blending correctly. Not setting the framebuffer
             //leads to darkened, oversaturated colors.
-            gl::Enable(gl::FRAMEBUFFER_SRGB);
+            if self.caps.framebuffer_srgb {
+                if self.gamma_space {
+                    // The shaders output gamma encoded colors, which must be stored as they are.
+                    gl::Disable(gl::FRAMEBUFFER_SRGB);
+                } else {
+                    gl::Enable(gl::FRAMEBUFFER_SRGB);
+                }
+            }
             gl::Enable(gl::SCISSOR_TEST);
             gl::Enable(gl::BLEND);
             gl::BlendFunc(gl::ONE, gl::ONE_MINUS_SRC_ALPHA); // premultiplied alpha
+            gl::ActiveTexture(gl::TEXTURE0 + self.texture_unit as GLenum);
             gl::UseProgram(self.program);
+            if self.caps.legacy {
+                // The attributes are set per batch by `paint_batch`.
+                gl::BindBuffer(gl::ARRAY_BUFFER, self.vertex_buffer);
+                gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, self.index_buffer);
+            } else {
+                gl::BindVertexArray(self.vertex_array);
+            }
+
+            let (x, y) = (self.screen_rect.width(), self.screen_rect.height());
+            gl::Uniform2f(self.locations.u_screen_size, x, y);
+            gl::Uniform1i(self.locations.u_sampler, self.texture_unit as GLint);
+            gl::Uniform1i(self.locations.u_dither, self.dithering as GLint);
+            gl::Uniform1i(self.locations.u_gamma_space, self.gamma_space as GLint);
+            for (location, value) in self.uniforms.values() {
+                value.apply(*location);
+            }
+            gl::Viewport(viewport[0], viewport[1], viewport[2], viewport[3]);
+        }
+    }
+
+    fn set_clip_rect(&self, clip_rect: Rect) {
+        let [surface_x, surface_y, canvas_width, canvas_height] = self.surface_viewport();
+        let pixels_per_point = self.pixels_per_point;
+        let screen_x = canvas_width as f32;
+        let screen_y = canvas_height as f32;
+
+        let clip_min_x = pixels_per_point * clip_rect.min.x;
+        let clip_min_y = pixels_per_point * clip_rect.min.y;
+        let clip_max_x = pixels_per_point * clip_rect.max.x;
+        let clip_max_y = pixels_per_point * clip_rect.max.y;
+        let clip_min_x = clip_min_x.clamp(0.0, screen_x);
+        let clip_min_y = clip_min_y.clamp(0.0, screen_y);
+        let clip_max_x = clip_max_x.clamp(clip_min_x, screen_x);
+        let clip_max_y = clip_max_y.clamp(clip_min_y, screen_y);
+        let clip_min_x = clip_min_x.round() as i32;
+        let clip_min_y = clip_min_y.round() as i32;
+        let clip_max_x = clip_max_x.round() as i32;
+        let clip_max_y = clip_max_y.round() as i32;
+
+        unsafe {
+            //scissor Y coordinate is from the bottom
+            gl::Scissor(
+                surface_x + clip_min_x,
+                surface_y + canvas_height - clip_max_y,
+                clip_max_x - clip_min_x,
+                clip_max_y - clip_min_y,
+            );
+        }
+    }
+
+    fn paint_callback(&self, callback: &egui::PaintCallback, clip_rect: Rect) {
+        let [surface_x, surface_y, _, _] = self.surface_viewport();
+        let (canvas_width, canvas_height) = self.surface_rect().size;
+        let info = PaintCallbackInfo {
+            viewport: callback.rect,
+            clip_rect,
+            pixels_per_point: self.pixels_per_point,
+            screen_size_px: [canvas_width, canvas_height],
+        };
+
+        // Callbacks meant for other backends are skipped, as egui documents.
+        if let Some(callback) = callback.callback.downcast_ref::<CallbackFn>() {
+            let viewport_px = info.viewport_in_pixels();
+            unsafe {
+                gl::Viewport(
+                    surface_x + viewport_px.left_px,
+                    surface_y + viewport_px.from_bottom_px,
+                    viewport_px.width_px,
+                    viewport_px.height_px,
+                );
+            }
+            self.set_clip_rect(clip_rect);
+
+            (callback.f)(info, self);
+
+            // The callback may have changed any GL state, so set ours up again.
+            self.prepare_painting();
+        }
+    }
+
+    /// Binds the multisampled framebuffer, cleared to transparent, if MSAA is on. Returns the
+    /// draw and read framebuffers that were bound, which the frame is resolved onto.
+    fn begin_msaa(&mut self) -> Option<(GLuint, GLuint)> {
+        if self.msaa_samples == 0 {
+            return None;
+        }
+        let mut draw_framebuffer = 0;
+        let mut read_framebuffer = 0;
+        unsafe {
+            gl::GetIntegerv(gl::DRAW_FRAMEBUFFER_BINDING, &mut draw_framebuffer);
+            gl::GetIntegerv(gl::READ_FRAMEBUFFER_BINDING, &mut read_framebuffer);
+        }
+        let framebuffers = (draw_framebuffer as GLuint, read_framebuffer as GLuint);
+
+        // Match the encoding of the target, so the resolved pixels can be copied over as is.
+        let srgb = self.target_framebuffer_info().srgb;
+        let (size, samples) = (self.canvas_size, self.msaa_samples);
+        let cached = self.msaa_targets.iter().position(|msaa| {
+            msaa.resolve.size == size && msaa.samples == samples && msaa.srgb == srgb
+        });
+        let msaa = match cached {
+            Some(index) => Some(self.msaa_targets.remove(index)),
+            None => MsaaTarget::new(size, samples, srgb),
+        };
+        let framebuffer = match msaa {
+            Some(msaa) => {
+                let framebuffer = msaa.framebuffer;
+                self.msaa_targets.insert(0, msaa);
+                if self.msaa_targets.len() > MAX_MSAA_TARGETS {
+                    for stale in self.msaa_targets.drain(MAX_MSAA_TARGETS..) {
+                        stale.delete();
+                    }
+                }
+                framebuffer
+            }
+            None => {
+                // The driver refused, paint straight into the target from now on.
+                self.msaa_samples = 0;
+                for msaa in self.msaa_targets.drain(..) {
+                    msaa.delete();
+                }
+                unsafe {
+                    gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, framebuffers.0);
+                    gl::BindFramebuffer(gl::READ_FRAMEBUFFER, framebuffers.1);
+                }
+                return None;
+            }
+        };
+
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, framebuffer);
+            gl::Disable(gl::SCISSOR_TEST);
+            gl::ClearColor(0.0, 0.0, 0.0, 0.0);
+            gl::Clear(gl::COLOR_BUFFER_BIT);
+        }
+        Some(framebuffers)
+    }
+
+    /// Resolves the multisampled frame onto `framebuffers`, the draw and read framebuffers from
+    /// [`Painter::begin_msaa`]. If `opaque` the frame replaces what the target holds, and is
+    /// blitted straight onto it where possible. Otherwise it is resolved into a texture, which
+    /// is blended over the target like egui's meshes would be.
+    fn resolve_msaa(&mut self, framebuffers: (GLuint, GLuint), opaque: bool) {
+        unsafe { gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, framebuffers.0) };
+        let target = self.target_framebuffer_info();
+        let (msaa, composite) = match (self.msaa_targets.first(), &self.msaa_composite) {
+            (Some(msaa), Some(composite)) => (msaa, composite),
+            _ => return,
+        };
+        let [x, y, width, height] = self.surface_viewport();
+        let blit = |draw_framebuffer| unsafe {
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, msaa.framebuffer);
+            gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, draw_framebuffer);
+            gl::BlitFramebuffer(
+                x,
+                y,
+                x + width,
+                y + height,
+                x,
+                y,
+                x + width,
+                y + height,
+                gl::COLOR_BUFFER_BIT,
+                gl::NEAREST,
+            );
+        };
+
+        unsafe {
+            // Blits are scissored, and a raw copy must not be converted.
+            gl::Disable(gl::SCISSOR_TEST);
+            if self.caps.framebuffer_srgb {
+                gl::Disable(gl::FRAMEBUFFER_SRGB);
+            }
+        }
+        // Multisampled targets can't be blitted onto, and GLES only blits between identical
+        // formats, which the window's isn't guaranteed to be.
+        if opaque && !target.multisampled && self.caps.context != GlContextKind::Es {
+            blit(framebuffers.0);
+            unsafe {
+                gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, framebuffers.0);
+                gl::BindFramebuffer(gl::READ_FRAMEBUFFER, framebuffers.1);
+            }
+            return;
+        }
+
+        blit(msaa.resolve.framebuffer);
+        unsafe {
+            gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, framebuffers.0);
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, framebuffers.1);
+
+            // The resolved colors are encoded like the target. Sampling decodes an sRGB texture,
+            // so either the framebuffer encodes it again or the shader does.
+            let encode_srgb = msaa.srgb && self.caps.framebuffer_srgb && self.gamma_space;
+            if self.caps.framebuffer_srgb && !self.gamma_space {
+                gl::Enable(gl::FRAMEBUFFER_SRGB);
+            }
+            gl::Enable(gl::SCISSOR_TEST);
+            gl::Scissor(x, y, width, height);
+            gl::Viewport(x, y, width, height);
+            if opaque {
+                gl::Disable(gl::BLEND);
+            } else {
+                gl::Enable(gl::BLEND);
+                gl::BlendFunc(gl::ONE, gl::ONE_MINUS_SRC_ALPHA); // premultiplied alpha
+            }
+            gl::UseProgram(composite.program);
+            gl::Uniform1i(composite.u_sampler, self.texture_unit as GLint);
+            gl::Uniform1i(composite.u_encode_srgb, encode_srgb as GLint);
+            gl::ActiveTexture(gl::TEXTURE0 + self.texture_unit as GLenum);
+            gl::BindTexture(gl::TEXTURE_2D, msaa.resolve.texture);
+            // The quad comes from the vertex ids, but core contexts still want a vertex array.
+            gl::BindVertexArray(self.vertex_array);
+            gl::DrawArrays(gl::TRIANGLE_STRIP, 0, 4);
+        }
+        self.frame_stats.draw_calls += 1;
+    }
+
+    /// Paints a frame into an offscreen texture of the given size (in pixels) instead of the
+    /// currently bound framebuffer, and returns the GL id of that texture.
+    ///
+    /// The texture is owned by the painter and reused between calls; it is only reallocated
+    /// when `size` changes, so the returned id may change too. It has an `SRGB8_ALPHA8` format
+    /// and the usual GL bottom-left origin. If `bg_color` is `None` the texture is cleared to
+    /// transparent. The previously bound framebuffer and viewport are restored afterwards.
+    pub fn paint_jobs_to_texture(
+        &mut self,
+        size: (u32, u32),
+        bg_color: Option<Color32>,
+        textures_delta: egui::TexturesDelta,
+        primitives: Vec<ClippedPrimitive>,
+    ) -> GLuint {
+        let mut prev_framebuffer = 0;
+        let mut prev_viewport = [0; 4];
+        unsafe {
+            gl::GetIntegerv(gl::FRAMEBUFFER_BINDING, &mut prev_framebuffer);
+            gl::GetIntegerv(gl::VIEWPORT, prev_viewport.as_mut_ptr());
+        }
+
+        match self.render_target.take() {
+            Some(target) if target.size == size => self.render_target = Some(target),
+            Some(target) => target.delete(),
+            None => {}
+        }
+        let target = self
+            .render_target
+            // SRGB8_ALPHA8 so that the linear output of the shaders is encoded the same way it
+            // would be when painting to an sRGB capable window.
+            .get_or_insert_with(|| RenderTarget::new(size, gl::SRGB8_ALPHA8));
+        let (framebuffer, texture) = (target.framebuffer, target.texture);
+
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, framebuffer);
+        }
+
+        let canvas_size = self.canvas_size;
+        let screen_rect = self.screen_rect;
+        // The texture is painted whole.
+        let surface = self.surface.take();
+        self.update_screen_rect(size);
+
+        let bg_color = bg_color.unwrap_or(Color32::TRANSPARENT);
+        self.paint_jobs(Some(bg_color), textures_delta, primitives);
+
+        self.canvas_size = canvas_size;
+        self.screen_rect = screen_rect;
+        self.surface = surface;
+
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, prev_framebuffer as GLuint);
+            gl::Viewport(
+                prev_viewport[0],
+                prev_viewport[1],
+                prev_viewport[2],
+                prev_viewport[3],
+            );
+        }
 
-            let u_screen_size = CString::new("u_screen_size").unwrap();
-            let u_screen_size_ptr = u_screen_size.as_ptr();
-            let u_screen_size_loc = gl::GetUniformLocation(self.program, u_screen_size_ptr);
+        texture
+    }
 
-            let (x, y) = (self.screen_rect.width(), self.screen_rect.height());
-            gl::Uniform2f(u_screen_size_loc, x, y);
+    /// The GL id of the texture last painted by [`Painter::paint_jobs_to_texture`], if any.
+    pub fn render_target_texture(&self) -> Option<GLuint> {
+        self.render_target.as_ref().map(|target| target.texture)
+    }
 
-            let u_sampler = CString::new("u_sampler").unwrap();
-            let u_sampler_ptr = u_sampler.as_ptr();
-            let u_sampler_loc = gl::GetUniformLocation(self.program, u_sampler_ptr);
-            gl::Uniform1i(u_sampler_loc, 0);
-            gl::Viewport(0, 0, canvas_width as i32, canvas_height as i32);
+    /// Reads back the pixels inside `rect` (in points) from the currently bound read
+    /// framebuffer, e.g. right after [`Painter::paint_jobs`] and before swapping the window.
+    ///
+    /// Rows are flipped so the image has a top-left origin like egui expects. If the framebuffer
+    /// is not sRGB encoded, the linear values the shaders wrote are gamma-encoded first, so the
+    /// image looks the same either way.
+    pub fn read_screen_rgba(&self, rect: Rect) -> ColorImage {
+        let [surface_x, surface_y, _, _] = self.surface_viewport();
+        let (canvas_width, canvas_height) = self.surface_rect().size;
+        let rect = rect.intersect(self.screen_rect);
+        let viewport_px = ViewportInPixels::from_points(
+            &rect,
+            self.pixels_per_point,
+            [canvas_width, canvas_height],
+        );
+        let width = viewport_px.width_px.max(0) as usize;
+        let height = viewport_px.height_px.max(0) as usize;
+        if width == 0 || height == 0 {
+            return ColorImage::new([width, height], vec![]);
+        }
 
-            let screen_x = canvas_width as f32;
-            let screen_y = canvas_height as f32;
+        let mut pixels = vec![0u8; width * height * 4];
+        unsafe {
+            gl::PixelStorei(gl::PACK_ALIGNMENT, 4);
+            gl::ReadPixels(
+                surface_x + viewport_px.left_px,
+                surface_y + viewport_px.from_bottom_px,
+                width as i32,
+                height as i32,
+                gl::RGBA,
+                gl::UNSIGNED_BYTE,
+                pixels.as_mut_ptr() as *mut gl::types::GLvoid,
+            );
+        }
 
-            for ClippedPrimitive {
-                clip_rect,
-                primitive,
-            } in primitives
-            {
-                match primitive {
-                    Primitive::Mesh(mesh) => {
-                        if let Some(Texture {
-                            gl_id: Some(texture_gl_id),
-                            ..
-                        }) = self.textures.get(&mesh.texture_id)
-                        {
-                            {
-                                gl::BindTexture(gl::TEXTURE_2D, *texture_gl_id);
-
-                                let clip_min_x = pixels_per_point * clip_rect.min.x;
-                                let clip_min_y = pixels_per_point * clip_rect.min.y;
-                                let clip_max_x = pixels_per_point * clip_rect.max.x;
-                                let clip_max_y = pixels_per_point * clip_rect.max.y;
-                                let clip_min_x = clip_min_x.clamp(0.0, x);
-                                let clip_min_y = clip_min_y.clamp(0.0, y);
-                                let clip_max_x = clip_max_x.clamp(clip_min_x, screen_x);
-                                let clip_max_y = clip_max_y.clamp(clip_min_y, screen_y);
-                                let clip_min_x = clip_min_x.round() as i32;
-                                let clip_min_y = clip_min_y.round() as i32;
-                                let clip_max_x = clip_max_x.round() as i32;
-                                let clip_max_y = clip_max_y.round() as i32;
-
-                                //scissor Y coordinate is from the bottom
-                                gl::Scissor(
-                                    clip_min_x,
-                                    canvas_height as i32 - clip_max_y,
-                                    clip_max_x - clip_min_x,
-                                    clip_max_y - clip_min_y,
-                                );
-
-                                self.paint_mesh(&mesh);
-                            }
-                        }
-                    }
-                    Primitive::Callback(_) => panic!("custom rendering not yet supported"),
-                }
+        let gamma_encode = !self.gamma_space && !Self::framebuffer_is_srgb(gl::READ_FRAMEBUFFER);
+        let mut flipped = Vec::with_capacity(width * height);
+        for row in pixels.chunks_exact(width * 4).rev() {
+            for p in row.chunks_exact(4) {
+                let color = if gamma_encode {
+                    Color32::from_rgba_premultiplied(
+                        egui::ecolor::gamma_u8_from_linear_f32(p[0] as f32 / 255.0),
+                        egui::ecolor::gamma_u8_from_linear_f32(p[1] as f32 / 255.0),
+                        egui::ecolor::gamma_u8_from_linear_f32(p[2] as f32 / 255.0),
+                        p[3],
+                    )
+                } else {
+                    Color32::from_rgba_premultiplied(p[0], p[1], p[2], p[3])
+                };
+                flipped.push(color);
             }
-
-            gl::BindTexture(gl::TEXTURE_2D, 0);
-            gl::Disable(gl::SCISSOR_TEST);
-            gl::Disable(gl::FRAMEBUFFER_SRGB);
-            gl::Disable(gl::BLEND);
         }
 
-        for texture_id in textures_delta.free {
-            self.free_texture(texture_id);
+        ColorImage::new([width, height], flipped)
+    }
+
+    /// Whether the color buffer of the framebuffer bound to `target`, `READ_FRAMEBUFFER` or
+    /// `DRAW_FRAMEBUFFER`, stores sRGB encoded values. Assumes it does if the driver won't tell.
+    fn framebuffer_is_srgb(target: GLenum) -> bool {
+        unsafe {
+            let binding = if target == gl::DRAW_FRAMEBUFFER {
+                gl::DRAW_FRAMEBUFFER_BINDING
+            } else {
+                gl::READ_FRAMEBUFFER_BINDING
+            };
+            let mut framebuffer = 0;
+            gl::GetIntegerv(binding, &mut framebuffer);
+            let attachment = if framebuffer == 0 {
+                gl::BACK_LEFT
+            } else {
+                gl::COLOR_ATTACHMENT0
+            };
+
+            // Clear any stale error so the check below only sees ours.
+            gl::GetError();
+            let mut encoding = gl::SRGB as GLint;
+            gl::GetFramebufferAttachmentParameteriv(
+                target,
+                attachment,
+                gl::FRAMEBUFFER_ATTACHMENT_COLOR_ENCODING,
+                &mut encoding,
+            );
+            gl::GetError() != gl::NO_ERROR || encoding == gl::SRGB as GLint
         }
     }
 
     pub fn cleanup(&self) {
+        if let Some(target) = &self.render_target {
+            target.delete();
+        }
+        for msaa in &self.msaa_targets {
+            msaa.delete();
+        }
+        if let Some(composite) = &self.msaa_composite {
+            composite.delete();
+        }
+        if let Some(timer) = &self.gpu_timer {
+            timer.delete();
+        }
         unsafe {
-            gl::DeleteSync(self.gl_sync_fence);
+            if !self.gl_sync_fence.is_null() {
+                gl::DeleteSync(self.gl_sync_fence);
+            }
             for (_, texture) in self.textures.iter() {
-                if let Some(texture_gl_id) = texture.gl_id {
-                    gl::DeleteTextures(1, &texture_gl_id);
-                }
+                Self::delete_gl_objects(texture);
             }
 
             gl::DeleteProgram(self.program);
+            if let Some((builtin_program, _)) = &self.builtin_program {
+                gl::DeleteProgram(*builtin_program);
+            }
             gl::DeleteBuffers(1, &self.vertex_buffer);
             gl::DeleteBuffers(1, &self.index_buffer);
-            gl::DeleteVertexArrays(1, &self.vertex_array);
+            if !self.caps.legacy {
+                gl::DeleteVertexArrays(1, &self.vertex_array);
+            }
         }
     }
 
@@ -433,40 +3060,44 @@ impl Painter {
 
         if let Some(patch_pos) = delta.pos {
             if let Some(texture) = self.textures.get_mut(&id) {
-                let patch_x = patch_pos[0];
-                let patch_y = patch_pos[1];
-                let patch_width = texture_width;
-                let patch_height = texture_height;
-                if texture.gl_id.is_some() {
+                if let Some(texture_gl_id) = texture.gl_id {
                     unsafe {
-                        let mipmap_level = 0;
-                        let internal_format = gl::RGBA;
-                        let texture_type = gl::UNSIGNED_BYTE;
-
-                        gl::TexSubImage2D(
-                            texture.gl_id.unwrap(),
-                            mipmap_level,
-                            patch_x as i32,
-                            patch_y as i32,
-                            patch_width as i32,
-                            patch_height as i32,
-                            internal_format,
-                            texture_type,
-                            pixels.as_ptr() as *const gl::types::GLvoid,
-                        );
+                        gl::BindTexture(gl::TEXTURE_2D, texture_gl_id);
+                    }
+                    let options = self.caps.texture_options(&delta.options, texture.size);
+                    if texture.options != delta.options {
+                        Self::apply_texture_options(&options);
+                        texture.options = delta.options;
+                    }
+
+                    Self::use_gl_texture2d_region(
+                        &pixels,
+                        texture_width,
+                        [0, 0],
+                        patch_pos,
+                        [texture_width, texture_height],
+                        &texture.format,
+                        self.caps.unpack_row_length,
+                    );
+                    self.frame_stats.count_texture_upload(pixels.len());
+                    if options.mipmap_mode.is_some() {
+                        unsafe { gl::GenerateMipmap(gl::TEXTURE_2D) };
                     }
                 }
             }
         } else {
-            let texture_filtering: bool = true;
-            let mut texture_gl_id = Option::None;
+            // Reuse the GL texture if egui is replacing the whole image of an existing one.
+            let mut texture_gl_id = self.textures.get(&id).and_then(|texture| texture.gl_id);
             Self::use_gl_texture2d(
                 &mut texture_gl_id,
                 &pixels,
                 texture_width as i32,
                 texture_height as i32,
-                texture_filtering,
+                &delta.options,
+                &UserTextureFormat::RGBA8_SRGB,
+                &self.caps,
             );
+            self.frame_stats.count_texture_upload(pixels.len());
 
             self.textures.insert(
                 id,
@@ -474,153 +3105,422 @@ impl Painter {
                     size: (texture_width, texture_height),
                     pixels,
                     gl_id: texture_gl_id,
-                    filtering: true,
+                    options: delta.options,
                     dirty: false,
+                    ..Default::default()
                 },
             );
         }
     }
 
     fn upload_user_textures(&mut self) {
+        let caps = &self.caps;
+        let stats = &mut self.frame_stats;
         for (_, texture) in self.textures.iter_mut() {
+            let format = caps.upload_format(&texture.format);
+            if let (Some(stream), Some(gl_id)) = (&mut texture.stream, texture.gl_id) {
+                unsafe { gl::BindTexture(gl::TEXTURE_2D, gl_id) };
+                if stream.upload(texture.size, &format) {
+                    let (width, height) = texture.size;
+                    stats.count_texture_upload(width * height * format.bytes_per_texel());
+                    let options = caps.texture_options(&texture.options, texture.size);
+                    if options.mipmap_mode.is_some() {
+                        unsafe { gl::GenerateMipmap(gl::TEXTURE_2D) };
+                    }
+                }
+                continue;
+            }
             if !texture.dirty {
+                if let (Some(gl_id), false) = (texture.gl_id, texture.dirty_regions.is_empty()) {
+                    unsafe {
+                        gl::BindTexture(gl::TEXTURE_2D, gl_id);
+                    }
+                    for (pos, size) in texture.dirty_regions.drain(..) {
+                        Self::use_gl_texture2d_region(
+                            &texture.pixels,
+                            texture.size.0,
+                            pos,
+                            pos,
+                            size,
+                            &format,
+                            caps.unpack_row_length,
+                        );
+                        stats.count_texture_upload(size[0] * size[1] * format.bytes_per_texel());
+                    }
+                    // Regions invalidate the mip chain.
+                    let options = caps.texture_options(&texture.options, texture.size);
+                    if options.mipmap_mode.is_some() {
+                        unsafe { gl::GenerateMipmap(gl::TEXTURE_2D) };
+                    }
+                }
                 continue;
             }
 
             let width = texture.size.0 as i32;
             let height = texture.size.1 as i32;
-            let filtering = texture.filtering;
             let mut gl_id = texture.gl_id;
-            Self::use_gl_texture2d(&mut gl_id, &texture.pixels, width, height, filtering);
+            Self::use_gl_texture2d(
+                &mut gl_id,
+                &texture.pixels,
+                width,
+                height,
+                &texture.options,
+                &format,
+                caps,
+            );
+            caps.apply_anisotropy(texture.anisotropy);
+            stats.count_texture_upload(texture.pixels.len());
 
             texture.gl_id = gl_id;
             texture.dirty = false;
+            texture.dirty_regions.clear();
+        }
+    }
+
+    /// Describes the vertex layout to `vertex_array`. Only needs to happen once, since the
+    /// buffers are reused (and just grown) every frame.
+    fn setup_vertex_array(
+        vertex_array: GLuint,
+        vertex_buffer: GLuint,
+        index_buffer: GLuint,
+        locations: &ProgramLocations,
+    ) {
+        unsafe {
+            gl::BindVertexArray(vertex_array);
+            gl::BindBuffer(gl::ARRAY_BUFFER, vertex_buffer);
+            Self::set_vertex_attribs(locations, 0);
+            gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, index_buffer);
         }
     }
 
-    fn paint_mesh(&self, mesh: &Mesh) {
-        debug_assert!(mesh.is_valid());
+    /// Points the attributes at the bound vertex buffer, starting at `base_vertex`.
+    fn set_vertex_attribs(locations: &ProgramLocations, base_vertex: usize) {
+        let stride: i32 = mem::size_of::<Vertex>().try_into().unwrap();
+        let base_offset = base_vertex * mem::size_of::<Vertex>();
+        let attributes = [
+            (
+                locations.a_pos,
+                2,
+                gl::FLOAT,
+                memoffset::offset_of!(Vertex, pos),
+            ),
+            (
+                locations.a_tc,
+                2,
+                gl::FLOAT,
+                memoffset::offset_of!(Vertex, uv),
+            ),
+            (
+                locations.a_srgba,
+                4,
+                gl::UNSIGNED_BYTE,
+                memoffset::offset_of!(Vertex, color),
+            ),
+        ];
+
         unsafe {
-            let indices = &mesh.indices;
-            let vertices = &mesh.vertices;
+            for (loc, size, ty, offset) in attributes {
+                let offset = (base_offset + offset) as *const gl::types::GLvoid;
+                gl::VertexAttribPointer(loc, size, ty, gl::FALSE, stride, offset);
+                gl::EnableVertexAttribArray(loc);
+            }
+        }
+    }
 
-            // --------------------------------------------------------------------
+    /// Gathers the meshes of a frame into batches and uploads all of their vertices and
+    /// indices at once. Consecutive meshes with the same texture and clip rect are merged
+    /// into a single batch. Expects the vertex array to be bound.
+    ///
+    /// On the legacy path batches are kept below 65536 vertices, so they can be drawn with
+    /// u16 indices.
+    fn upload_primitives(&mut self, primitives: Vec<ClippedPrimitive>) -> Vec<PaintBatch> {
+        self.vertices.clear();
+        self.indices.clear();
+        let mut batches = Vec::new();
+
+        let debug = self.debug_modes;
+        // Debug geometry is drawn with the white texel of the font texture.
+        let white_texture = self.texture_gl_info(TextureId::default());
+        let line_width = 1.0 / self.pixels_per_point;
+        let mut wireframes = Vec::new();
+        let mut clip_outlines = Mesh::default();
+        let mut last_clip_rect = None;
+
+        for ClippedPrimitive {
+            clip_rect,
+            primitive,
+        } in primitives
+        {
+            if debug.clip_rects && last_clip_rect != Some(clip_rect) {
+                DebugModes::add_rect_outline(
+                    &mut clip_outlines,
+                    clip_rect,
+                    line_width,
+                    DebugModes::CLIP_RECT_COLOR,
+                );
+                last_clip_rect = Some(clip_rect);
+            }
 
-            gl::BindVertexArray(self.vertex_array);
-            gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, self.index_buffer);
-            gl::BufferData(
-                gl::ELEMENT_ARRAY_BUFFER,
-                (indices.len() * mem::size_of::<u32>()) as GLsizeiptr,
-                indices.as_ptr() as *const gl::types::GLvoid,
-                gl::STREAM_DRAW,
-            );
+            match primitive {
+                Primitive::Mesh(mut mesh) => {
+                    debug_assert!(mesh.is_valid());
+                    let mut texture = match self.texture_gl_info(mesh.texture_id) {
+                        Some(texture) => texture,
+                        None => continue,
+                    };
+                    if mesh.indices.is_empty() {
+                        continue;
+                    }
+                    self.frame_stats.meshes += 1;
+
+                    if debug.wireframe {
+                        wireframes.push((clip_rect, DebugModes::wireframe(&mesh, line_width)));
+                    }
+                    if let Some(white_texture) = white_texture.filter(|_| debug.overdraw) {
+                        for vertex in &mut mesh.vertices {
+                            vertex.uv = WHITE_UV;
+                            vertex.color = DebugModes::OVERDRAW_COLOR;
+                        }
+                        texture = white_texture;
+                    } else if debug.tint_textures {
+                        let [r, g, b, _] = DebugModes::texture_tint(mesh.texture_id).to_array();
+                        for vertex in &mut mesh.vertices {
+                            let [vr, vg, vb, va] = vertex.color.to_array();
+                            let tint = |c: u8, t: u8| (c as u16 * t as u16 / 255) as u8;
+                            vertex.color = Color32::from_rgba_premultiplied(
+                                tint(vr, r),
+                                tint(vg, g),
+                                tint(vb, b),
+                                va,
+                            );
+                        }
+                    }
+                    self.push_clipped_mesh(&mut batches, texture, clip_rect, mesh);
+                }
+                Primitive::Callback(callback) => {
+                    if callback.rect.is_positive() {
+                        batches.push(PaintBatch::Callback(callback, clip_rect));
+                    }
+                }
+            }
+        }
 
-            // --------------------------------------------------------------------
+        // Overlays go on top of everything.
+        if let Some(white_texture) = white_texture {
+            for (clip_rect, wireframe) in wireframes {
+                self.push_clipped_mesh(&mut batches, white_texture, clip_rect, wireframe);
+            }
+            let screen_rect = self.screen_rect;
+            self.push_clipped_mesh(&mut batches, white_texture, screen_rect, clip_outlines);
+        }
 
+        unsafe {
             gl::BindBuffer(gl::ARRAY_BUFFER, self.vertex_buffer);
-            gl::BufferData(
+            Self::upload_stream_buffer(
                 gl::ARRAY_BUFFER,
-                (vertices.len() * mem::size_of::<egui::epaint::Vertex>()) as GLsizeiptr,
-                vertices.as_ptr() as *const gl::types::GLvoid,
-                gl::STREAM_DRAW,
+                &mut self.vertex_buffer_capacity,
+                &self.vertices,
             );
-            let stride: i32 = mem::size_of::<egui::epaint::Vertex>().try_into().unwrap();
+            gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, self.index_buffer);
+            if self.caps.legacy {
+                self.indices_u16.clear();
+                self.indices_u16
+                    .extend(self.indices.iter().map(|&index| index as u16));
+                Self::upload_stream_buffer(
+                    gl::ELEMENT_ARRAY_BUFFER,
+                    &mut self.index_buffer_capacity,
+                    &self.indices_u16,
+                );
+            } else {
+                Self::upload_stream_buffer(
+                    gl::ELEMENT_ARRAY_BUFFER,
+                    &mut self.index_buffer_capacity,
+                    &self.indices,
+                );
+            }
+        }
 
-            let a_pos = CString::new("a_pos").unwrap();
-            let a_pos_ptr = a_pos.as_ptr();
-            let a_pos_loc = gl::GetAttribLocation(self.program, a_pos_ptr);
-            assert!(a_pos_loc >= 0);
-            let a_pos_loc = a_pos_loc as u32;
+        let index_size = if self.caps.legacy {
+            mem::size_of::<u16>()
+        } else {
+            mem::size_of::<u32>()
+        };
+        self.frame_stats.vertices += self.vertices.len();
+        self.frame_stats.indices += self.indices.len();
+        self.frame_stats.bytes_uploaded +=
+            self.vertices.len() * mem::size_of::<Vertex>() + self.indices.len() * index_size;
 
-            gl::VertexAttribPointer(
-                a_pos_loc,
-                2,
-                gl::FLOAT,
-                gl::FALSE,
-                stride,
-                memoffset::offset_of!(egui::epaint::Vertex, pos) as *const _,
-            );
-            gl::EnableVertexAttribArray(a_pos_loc);
+        batches
+    }
 
-            let a_tc = CString::new("a_tc").unwrap();
-            let a_tc_ptr = a_tc.as_ptr();
-            let a_tc_loc = gl::GetAttribLocation(self.program, a_tc_ptr);
-            assert!(a_tc_loc >= 0);
-            let a_tc_loc = a_tc_loc as u32;
+    /// The GL id of an uploaded texture and whether it holds linear colors.
+    fn texture_gl_info(&self, id: TextureId) -> Option<(GLuint, bool)> {
+        let texture = self.textures.get(&id)?;
+        let linear = texture.format.color_space == TextureColorSpace::Linear;
+        Some((texture.gl_id?, linear))
+    }
 
-            gl::VertexAttribPointer(
-                a_tc_loc,
-                2,
-                gl::FLOAT,
-                gl::FALSE,
-                stride,
-                memoffset::offset_of!(egui::epaint::Vertex, uv) as *const _,
-            );
-            gl::EnableVertexAttribArray(a_tc_loc);
+    /// Appends `mesh` with [`Painter::push_mesh`], split up first on the legacy path if it has
+    /// too many vertices for u16 indices.
+    fn push_clipped_mesh(
+        &mut self,
+        batches: &mut Vec<PaintBatch>,
+        texture: (GLuint, bool),
+        clip_rect: Rect,
+        mesh: Mesh,
+    ) {
+        if mesh.indices.is_empty() {
+            return;
+        }
+        if self.caps.legacy && mesh.vertices.len() > MAX_U16_BATCH_VERTICES {
+            for mesh in mesh.split_to_u16() {
+                let indices: Vec<u32> = mesh.indices.iter().map(|&index| index as u32).collect();
+                self.push_mesh(batches, texture, clip_rect, &indices, &mesh.vertices);
+            }
+        } else {
+            self.push_mesh(batches, texture, clip_rect, &mesh.indices, &mesh.vertices);
+        }
+    }
 
-            let a_srgba = CString::new("a_srgba").unwrap();
-            let a_srgba_ptr = a_srgba.as_ptr();
-            let a_srgba_loc = gl::GetAttribLocation(self.program, a_srgba_ptr);
-            assert!(a_srgba_loc >= 0);
-            let a_srgba_loc = a_srgba_loc as u32;
+    /// Appends a mesh to the last batch if it has the same texture and clip rect (and, on the
+    /// legacy path, room for its vertices), or to a new batch otherwise. `texture` is the GL
+    /// id of the mesh's texture and whether it holds linear colors.
+    fn push_mesh(
+        &mut self,
+        batches: &mut Vec<PaintBatch>,
+        (texture_gl_id, texture_linear): (GLuint, bool),
+        clip_rect: Rect,
+        indices: &[u32],
+        vertices: &[Vertex],
+    ) {
+        let max_vertices = if self.caps.legacy {
+            MAX_U16_BATCH_VERTICES
+        } else {
+            usize::MAX
+        };
+        let vertex_count = self.vertices.len();
+        let extends_last = matches!(
+            batches.last(),
+            Some(PaintBatch::Mesh(batch))
+                if batch.texture_gl_id == texture_gl_id
+                    && batch.clip_rect == clip_rect
+                    && vertex_count - batch.base_vertex + vertices.len() <= max_vertices
+        );
+        if !extends_last {
+            batches.push(PaintBatch::Mesh(MeshBatch {
+                texture_gl_id,
+                texture_linear,
+                clip_rect,
+                first_index: self.indices.len(),
+                index_count: 0,
+                base_vertex: vertex_count,
+            }));
+        }
 
-            gl::VertexAttribPointer(
-                a_srgba_loc,
-                4,
-                gl::UNSIGNED_BYTE,
-                gl::FALSE,
-                stride,
-                memoffset::offset_of!(egui::epaint::Vertex, color) as *const _,
-            );
-            gl::EnableVertexAttribArray(a_srgba_loc);
+        if let Some(PaintBatch::Mesh(batch)) = batches.last_mut() {
+            let offset = (vertex_count - batch.base_vertex) as u32;
+            self.indices
+                .extend(indices.iter().map(|index| index + offset));
+            self.vertices.extend_from_slice(vertices);
+            batch.index_count += indices.len();
+        }
+    }
 
-            // --------------------------------------------------------------------
+    /// Uploads `data` to the buffer bound to `target`, growing it if needed.
+    fn upload_stream_buffer<T>(target: GLenum, capacity: &mut usize, data: &[T]) {
+        let size = mem::size_of_val(data);
+        if size == 0 {
+            return;
+        }
+        if size > *capacity {
+            *capacity = size.next_power_of_two();
+        }
 
-            gl::DrawElements(
-                gl::TRIANGLES,
-                indices.len() as i32,
-                gl::UNSIGNED_INT,
+        unsafe {
+            // Orphan the old storage so the driver doesn't wait on last frame's draw calls.
+            gl::BufferData(
+                target,
+                *capacity as GLsizeiptr,
                 ptr::null(),
+                gl::STREAM_DRAW,
+            );
+            gl::BufferSubData(
+                target,
+                0,
+                size as GLsizeiptr,
+                data.as_ptr() as *const gl::types::GLvoid,
+            );
+        }
+    }
+
+    fn paint_batch(&self, batch: &MeshBatch) {
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D, batch.texture_gl_id);
+            gl::Uniform1i(
+                self.locations.u_texture_linear,
+                batch.texture_linear as GLint,
             );
-            gl::DisableVertexAttribArray(a_pos_loc);
-            gl::DisableVertexAttribArray(a_tc_loc);
-            gl::DisableVertexAttribArray(a_srgba_loc);
+        }
+        self.set_clip_rect(batch.clip_rect);
+        unsafe {
+            if self.caps.legacy {
+                // No base vertex draws, so move the attributes to the batch's vertices instead.
+                Self::set_vertex_attribs(&self.locations, batch.base_vertex);
+                gl::DrawElements(
+                    gl::TRIANGLES,
+                    batch.index_count as i32,
+                    gl::UNSIGNED_SHORT,
+                    (batch.first_index * mem::size_of::<u16>()) as *const gl::types::GLvoid,
+                );
+            } else if !self.caps.base_vertex {
+                // GLES 3.0 and 3.1 still have vertex arrays, so only the offsets move.
+                Self::set_vertex_attribs(&self.locations, batch.base_vertex);
+                gl::DrawElements(
+                    gl::TRIANGLES,
+                    batch.index_count as i32,
+                    gl::UNSIGNED_INT,
+                    (batch.first_index * mem::size_of::<u32>()) as *const gl::types::GLvoid,
+                );
+            } else {
+                gl::DrawElementsBaseVertex(
+                    gl::TRIANGLES,
+                    batch.index_count as i32,
+                    gl::UNSIGNED_INT,
+                    (batch.first_index * mem::size_of::<u32>()) as *const gl::types::GLvoid,
+                    batch.base_vertex as i32,
+                );
+            }
         }
     }
 
+    /// Uploads a whole texture and generates its mipmaps if `options` asks for them and `caps`
+    /// allows it. `format` must be an upload format of `caps`, see
+    /// [`GlCapabilities::upload_format`].
     fn use_gl_texture2d(
         gl_id: &mut Option<GLuint>,
         pixels: &[u8],
         width: i32,
         height: i32,
-        filtering: bool,
+        options: &TextureOptions,
+        format: &UserTextureFormat,
+        caps: &GlCapabilities,
     ) {
         unsafe {
             if gl_id.is_none() {
                 let mut texture_id = 0;
                 gl::GenTextures(1, &mut texture_id);
-                gl::BindTexture(gl::TEXTURE_2D, texture_id);
-                gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
-                gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
-
-                if filtering {
-                    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
-                    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
-                } else {
-                    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as i32);
-                    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as i32);
-                }
-
                 *gl_id = Some(texture_id);
-            } else {
-                gl::BindTexture(gl::TEXTURE_2D, gl_id.unwrap());
+            }
+            gl::BindTexture(gl::TEXTURE_2D, gl_id.unwrap());
+            let options = caps.texture_options(options, (width as usize, height as usize));
+            Self::apply_texture_options(&options);
+            if caps.texture_swizzle {
+                Self::apply_texture_swizzle(&format.swizzle);
             }
 
             let mipmap_level = 0;
-            let internal_format = gl::RGBA;
+            let (internal_format, src_format, src_type) = format.gl_formats(caps.sized_formats);
             let border = 0;
-            let src_format = gl::RGBA;
-            let src_type = gl::UNSIGNED_BYTE;
 
             gl::TexImage2D(
                 gl::TEXTURE_2D,
@@ -633,6 +3533,142 @@ impl Painter {
                 src_type,
                 pixels.as_ptr() as *const gl::types::GLvoid,
             );
+
+            if options.mipmap_mode.is_some() {
+                gl::GenerateMipmap(gl::TEXTURE_2D);
+            }
+        }
+    }
+
+    /// Uploads `size` texels to `dst_pos` of the texture bound to `TEXTURE_2D`. The source is
+    /// the region at `src_pos` of `pixels`, an image in `format` that is `row_length` texels
+    /// wide, so a region can be uploaded straight out of a larger image without copying it
+    /// first. That needs `unpack_row_length`, without it the region is copied out. Mipmaps are
+    /// left for the caller to regenerate.
+    fn use_gl_texture2d_region(
+        pixels: &[u8],
+        row_length: usize,
+        src_pos: [usize; 2],
+        dst_pos: [usize; 2],
+        size: [usize; 2],
+        format: &UserTextureFormat,
+        unpack_row_length: bool,
+    ) {
+        let texel_size = format.bytes_per_texel();
+        if !unpack_row_length && (row_length != size[0] || src_pos != [0, 0]) {
+            let row_bytes = size[0] * texel_size;
+            let mut region = Vec::with_capacity(row_bytes * size[1]);
+            for row in src_pos[1]..src_pos[1] + size[1] {
+                let start = (row * row_length + src_pos[0]) * texel_size;
+                region.extend_from_slice(&pixels[start..start + row_bytes]);
+            }
+            return Self::use_gl_texture2d_region(
+                &region,
+                size[0],
+                [0, 0],
+                dst_pos,
+                size,
+                format,
+                unpack_row_length,
+            );
+        }
+        let (_, src_format, src_type) = format.gl_formats(false);
+
+        unsafe {
+            if unpack_row_length {
+                gl::PixelStorei(gl::UNPACK_ROW_LENGTH, row_length as i32);
+                gl::PixelStorei(gl::UNPACK_SKIP_PIXELS, src_pos[0] as i32);
+                gl::PixelStorei(gl::UNPACK_SKIP_ROWS, src_pos[1] as i32);
+            }
+
+            let mipmap_level = 0;
+            gl::TexSubImage2D(
+                gl::TEXTURE_2D,
+                mipmap_level,
+                dst_pos[0] as i32,
+                dst_pos[1] as i32,
+                size[0] as i32,
+                size[1] as i32,
+                src_format,
+                src_type,
+                pixels.as_ptr() as *const gl::types::GLvoid,
+            );
+
+            if unpack_row_length {
+                gl::PixelStorei(gl::UNPACK_ROW_LENGTH, 0);
+                gl::PixelStorei(gl::UNPACK_SKIP_PIXELS, 0);
+                gl::PixelStorei(gl::UNPACK_SKIP_ROWS, 0);
+            }
+        }
+    }
+
+    /// Sets the swizzle of the texture bound to `TEXTURE_2D`.
+    fn apply_texture_swizzle(swizzle: &[Swizzle; 4]) {
+        let targets = [
+            gl::TEXTURE_SWIZZLE_R,
+            gl::TEXTURE_SWIZZLE_G,
+            gl::TEXTURE_SWIZZLE_B,
+            gl::TEXTURE_SWIZZLE_A,
+        ];
+        for (target, swizzle) in targets.iter().zip(swizzle) {
+            let source = match swizzle {
+                Swizzle::R => gl::RED,
+                Swizzle::G => gl::GREEN,
+                Swizzle::B => gl::BLUE,
+                Swizzle::A => gl::ALPHA,
+                Swizzle::Zero => gl::ZERO,
+                Swizzle::One => gl::ONE,
+            };
+            unsafe { gl::TexParameteri(gl::TEXTURE_2D, *target, source as GLint) };
+        }
+    }
+
+    fn user_texture_options(filtering: bool) -> TextureOptions {
+        if filtering {
+            TextureOptions::LINEAR
+        } else {
+            TextureOptions::NEAREST
+        }
+    }
+
+    /// Runs `f` with `gl_id` bound to `TEXTURE_2D`, then binds back whatever texture was bound
+    /// before. For calls outside of painting, where the binding belongs to the app.
+    fn with_texture_bound<R>(gl_id: GLuint, f: impl FnOnce() -> R) -> R {
+        let mut prev_texture = 0;
+        unsafe {
+            gl::GetIntegerv(gl::TEXTURE_BINDING_2D, &mut prev_texture);
+            gl::BindTexture(gl::TEXTURE_2D, gl_id);
+        }
+        let result = f();
+        unsafe { gl::BindTexture(gl::TEXTURE_2D, prev_texture as GLuint) };
+        result
+    }
+
+    /// Sets the sampler state of the texture bound to `TEXTURE_2D` from egui's texture options.
+    fn apply_texture_options(options: &TextureOptions) {
+        let filter = |filter: TextureFilter| match filter {
+            TextureFilter::Nearest => gl::NEAREST,
+            TextureFilter::Linear => gl::LINEAR,
+        };
+        let mag_filter = filter(options.magnification);
+        let min_filter = match (options.minification, options.mipmap_mode) {
+            (minification, None) => filter(minification),
+            (TextureFilter::Nearest, Some(TextureFilter::Nearest)) => gl::NEAREST_MIPMAP_NEAREST,
+            (TextureFilter::Nearest, Some(TextureFilter::Linear)) => gl::NEAREST_MIPMAP_LINEAR,
+            (TextureFilter::Linear, Some(TextureFilter::Nearest)) => gl::LINEAR_MIPMAP_NEAREST,
+            (TextureFilter::Linear, Some(TextureFilter::Linear)) => gl::LINEAR_MIPMAP_LINEAR,
+        };
+        let wrap_mode = match options.wrap_mode {
+            TextureWrapMode::ClampToEdge => gl::CLAMP_TO_EDGE,
+            TextureWrapMode::Repeat => gl::REPEAT,
+            TextureWrapMode::MirroredRepeat => gl::MIRRORED_REPEAT,
+        };
+
+        unsafe {
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, mag_filter as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, min_filter as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, wrap_mode as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, wrap_mode as i32);
         }
     }
 }
@@ -642,3 +3678,224 @@ impl Drop for Painter {
         self.cleanup();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn caps(context: GlContextKind, glsl_version: u32, framebuffer_srgb: bool) -> GlCapabilities {
+        GlCapabilities {
+            context,
+            glsl_version,
+            legacy: false,
+            base_vertex: true,
+            framebuffer_srgb,
+            unpack_row_length: true,
+            texture_rg: true,
+            texture_swizzle: true,
+            sized_formats: true,
+            generate_mipmap: true,
+            npot_mipmaps: true,
+            max_anisotropy: None,
+            max_samples: 0,
+            timer_query: false,
+        }
+    }
+
+    fn auto(context: GlContextKind, glsl_version: u32) -> (ShaderVariant, String) {
+        let (variant, vs_src, fs_src) =
+            ShaderVariant::select(&ShaderVersion::Auto, &caps(context, glsl_version, true));
+        assert_eq!(vs_src.lines().next(), fs_src.lines().next());
+        (variant, vs_src)
+    }
+
+    #[test]
+    fn parse_version_desktop() {
+        let parse = GlCapabilities::parse_version;
+        assert_eq!(parse("4.6.0 NVIDIA 535.104.05"), Some(460));
+        assert_eq!(parse("3.3.0 - Build 31.0.101.2111"), Some(330));
+        assert_eq!(parse("2.1 Mesa 23.0.4"), Some(210));
+        assert_eq!(parse("4.60 NVIDIA"), Some(460));
+        assert_eq!(parse("1.50"), Some(150));
+        assert_eq!(parse("1.5"), Some(150));
+    }
+
+    #[test]
+    fn parse_version_es() {
+        let parse = GlCapabilities::parse_version;
+        assert_eq!(parse("OpenGL ES 3.0 Mesa 23.0.4"), Some(300));
+        assert_eq!(parse("OpenGL ES 2.0 (ANGLE 2.1.0)"), Some(200));
+        assert_eq!(parse("OpenGL ES GLSL ES 1.00"), Some(100));
+        assert_eq!(parse("OpenGL ES GLSL ES 3.20"), Some(320));
+    }
+
+    #[test]
+    fn parse_version_malformed() {
+        let parse = GlCapabilities::parse_version;
+        assert_eq!(parse(""), None);
+        assert_eq!(parse("OpenGL ES"), None);
+        assert_eq!(parse("version x.y"), None);
+        assert_eq!(parse("4."), None);
+        assert_eq!(parse(".6"), None);
+    }
+
+    #[test]
+    fn auto_picks_default_shaders_from_glsl_150() {
+        for context in [GlContextKind::Core, GlContextKind::Compatibility] {
+            for glsl_version in [150, 330, 460] {
+                let (variant, vs_src) = auto(context, glsl_version);
+                assert!(!variant.adaptive);
+                assert_eq!(variant.glsl_version, 150);
+                assert_eq!(variant.context, context);
+                assert_eq!(vs_src, DEFAULT_VERT_SRC);
+            }
+        }
+    }
+
+    #[test]
+    fn auto_picks_adaptive_shaders_below_glsl_150() {
+        for context in [GlContextKind::Core, GlContextKind::Compatibility] {
+            for (glsl_version, picked) in [(140, 140), (130, 120), (120, 120), (110, 110)] {
+                let (variant, vs_src) = auto(context, glsl_version);
+                assert!(variant.adaptive);
+                assert_eq!(variant.glsl_version, picked);
+                let version_line = format!("#version {}", picked);
+                assert_eq!(vs_src.lines().next(), Some(version_line.as_str()));
+            }
+        }
+    }
+
+    #[test]
+    fn auto_picks_glsl_es_300_or_100() {
+        let (variant, vs_src) = auto(GlContextKind::Es, 320);
+        assert_eq!(variant.glsl_version, 300);
+        assert_eq!(vs_src.lines().next(), Some("#version 300 es"));
+
+        let (variant, vs_src) = auto(GlContextKind::Es, 300);
+        assert_eq!(variant.glsl_version, 300);
+        assert_eq!(vs_src.lines().next(), Some("#version 300 es"));
+
+        let (variant, vs_src) = auto(GlContextKind::Es, 100);
+        assert!(variant.adaptive);
+        assert_eq!(variant.glsl_version, 100);
+        assert_eq!(vs_src.lines().next(), Some("#version 100"));
+    }
+
+    #[test]
+    fn explicit_versions_ignore_the_context() {
+        let es = caps(GlContextKind::Es, 300, true);
+        let (variant, vs_src, _) = ShaderVariant::select(&ShaderVersion::Default, &es);
+        assert!(!variant.adaptive);
+        assert_eq!(vs_src, DEFAULT_VERT_SRC);
+
+        let (variant, vs_src, _) = ShaderVariant::select(&ShaderVersion::Adaptive, &es);
+        assert_eq!((variant.adaptive, variant.glsl_version), (true, 100));
+        assert_eq!(vs_src, ADAPTIVE_VERT_SRC);
+
+        let core = caps(GlContextKind::Core, 460, true);
+        let (variant, _, _) = ShaderVariant::select(&ShaderVersion::Adaptive, &core);
+        assert_eq!((variant.adaptive, variant.glsl_version), (true, 110));
+    }
+
+    #[test]
+    fn adaptive_shaders_encode_without_framebuffer_srgb() {
+        let caps = caps(GlContextKind::Compatibility, 120, false);
+        let (_, vs_src, fs_src) = ShaderVariant::select(&ShaderVersion::Auto, &caps);
+        for src in [vs_src, fs_src] {
+            let header: Vec<&str> = src.lines().take(2).collect();
+            assert_eq!(header, ["#version 120", "#define NO_FRAMEBUFFER_SRGB"]);
+        }
+    }
+
+    fn format(
+        channels: TextureChannels,
+        component: TextureComponent,
+        swizzle: [Swizzle; 4],
+    ) -> UserTextureFormat {
+        UserTextureFormat {
+            channels,
+            component,
+            color_space: TextureColorSpace::Linear,
+            swizzle,
+        }
+    }
+
+    #[test]
+    fn expand_u8_fills_missing_channels() {
+        let identity = UserTextureFormat::IDENTITY_SWIZZLE;
+        let rgb8 = format(TextureChannels::Rgb, TextureComponent::U8, identity);
+        assert_eq!(
+            rgb8.expand(&[1, 2, 3, 4, 5, 6]),
+            [1, 2, 3, 255, 4, 5, 6, 255]
+        );
+        assert_eq!(
+            UserTextureFormat::RG8.expand(&[1, 2, 3, 4]),
+            [1, 2, 0, 255, 3, 4, 0, 255]
+        );
+        assert_eq!(
+            UserTextureFormat::R8_GRAYSCALE.expand(&[7, 9]),
+            [7, 7, 7, 255, 9, 9, 9, 255]
+        );
+
+        let expanded = rgb8.expanded();
+        assert_eq!(expanded.channels, TextureChannels::Rgba);
+        assert_eq!(expanded.swizzle, identity);
+        assert_eq!(expanded.bytes_per_texel(), 4);
+    }
+
+    #[test]
+    fn expand_applies_the_swizzle() {
+        use Swizzle::*;
+        let bgra = format(TextureChannels::Rgba, TextureComponent::U8, [B, G, R, A]);
+        assert_eq!(bgra.expand(&[1, 2, 3, 4]), [3, 2, 1, 4]);
+        let alpha_mask = format(TextureChannels::R, TextureComponent::U8, [One, One, One, R]);
+        assert_eq!(alpha_mask.expand(&[128]), [255, 255, 255, 128]);
+        let constants = format(TextureChannels::Rg, TextureComponent::U8, [G, Zero, A, B]);
+        assert_eq!(constants.expand(&[1, 2]), [2, 0, 255, 0]);
+    }
+
+    #[test]
+    fn expand_keeps_wider_components() {
+        let identity = UserTextureFormat::IDENTITY_SWIZZLE;
+        let bytes = |values: &[u16]| -> Vec<u8> {
+            values
+                .iter()
+                .flat_map(|value| value.to_ne_bytes())
+                .collect()
+        };
+        let rg16 = format(TextureChannels::Rg, TextureComponent::U16, identity);
+        assert_eq!(
+            rg16.expand(&bytes(&[1000, 2000])),
+            bytes(&[1000, 2000, 0, u16::MAX])
+        );
+
+        let bytes = |values: &[f32]| -> Vec<u8> {
+            values
+                .iter()
+                .flat_map(|value| value.to_ne_bytes())
+                .collect()
+        };
+        let r32f = format(
+            TextureChannels::R,
+            TextureComponent::F32,
+            [Swizzle::R, Swizzle::R, Swizzle::R, Swizzle::One],
+        );
+        assert_eq!(
+            r32f.expand(&bytes(&[0.25, 2.5])),
+            bytes(&[0.25, 0.25, 0.25, 1.0, 2.5, 2.5, 2.5, 1.0])
+        );
+    }
+
+    #[test]
+    fn texture_tint_handles_any_id() {
+        let tints = [
+            DebugModes::texture_tint(TextureId::Managed(u64::MAX)),
+            DebugModes::texture_tint(TextureId::User(u64::MAX)),
+        ];
+        assert!(tints.iter().all(|tint| tint.a() == 255));
+        assert_ne!(
+            DebugModes::texture_tint(TextureId::Managed(0)),
+            DebugModes::texture_tint(TextureId::User(0))
+        );
+    }
+}
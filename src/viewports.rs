@@ -0,0 +1,463 @@
+//! Native windows for egui's deferred and immediate viewports.
+//!
+//! Every viewport gets its own SDL2 window, and all of them are painted with the same GL
+//! context and [`Painter`], so textures are shared between the windows. The windows need to be
+//! made with `.opengl()` in the same pixel format as the root window, which is what
+//! [`Viewports`] does.
+//!
+//! The painter is shared through an `Rc<RefCell<_>>`, because immediate viewports are painted
+//! from inside [`egui::Context::run`] of their parent.
+use crate::painter::Painter;
+use crate::{translate_event, EguiStateHandler, FusedCursor};
+use egui::{
+    pos2, vec2, Context, DeferredViewportUiCallback, FullOutput, ImmediateViewport, Pos2, Rect,
+    ViewportBuilder, ViewportClass, ViewportCommand, ViewportEvent, ViewportId, ViewportIdMap,
+    ViewportOutput,
+};
+use sdl2::event::{Event, WindowEvent};
+use sdl2::video::{FullscreenType, Window, WindowPos};
+use std::cell::RefCell;
+use std::rc::{Rc, Weak};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// Size of a viewport window whose builder has no size, in points.
+const DEFAULT_INNER_SIZE: (f32, f32) = (400.0, 300.0);
+
+/// Opens, paints and closes an SDL2 window for every egui viewport other than the root one.
+///
+/// Create it once after the painter, forward SDL events to [`Viewports::process_input`] before
+/// the root [`EguiStateHandler`], and call [`Viewports::update`] with the root viewport's
+/// output every frame.
+pub struct Viewports {
+    windows: Rc<RefCell<ViewportWindows>>,
+    painter: Rc<RefCell<Painter>>,
+}
+
+struct ViewportWindows {
+    root_window: Window,
+    windows: ViewportIdMap<ViewportWindow>,
+    start_time: Instant,
+}
+
+struct ViewportWindow {
+    window: Window,
+    state: EguiStateHandler,
+    class: ViewportClass,
+    builder: ViewportBuilder,
+    viewport_ui_cb: Option<Arc<DeferredViewportUiCallback>>,
+    /// When a deferred viewport needs to run again without new input.
+    repaint_at: Option<Instant>,
+    /// Screenshots requested from outside the viewport's own pass, taken when it is next
+    /// painted.
+    screenshots: Vec<egui::UserData>,
+}
+
+impl Viewports {
+    /// Makes `ctx` open viewports as native windows next to `root_window`, and installs the
+    /// renderer for immediate viewports.
+    pub fn new(ctx: &Context, root_window: &Window, painter: Rc<RefCell<Painter>>) -> Viewports {
+        ctx.set_embed_viewports(false);
+        let windows = Rc::new(RefCell::new(ViewportWindows {
+            root_window: root_window.clone(),
+            windows: ViewportIdMap::default(),
+            start_time: Instant::now(),
+        }));
+
+        // The renderer is a thread local of egui, so it must not keep the windows alive.
+        let weak_windows = Rc::downgrade(&windows);
+        let weak_painter = Rc::downgrade(&painter);
+        Context::set_immediate_viewport_renderer(move |ctx, viewport| {
+            if let (Some(windows), Some(painter)) =
+                (Weak::upgrade(&weak_windows), Weak::upgrade(&weak_painter))
+            {
+                Viewports { windows, painter }.render_immediate(ctx, viewport);
+            }
+        });
+
+        Viewports { windows, painter }
+    }
+
+    /// Handles `event` if it belongs to one of the viewport windows. Returns false for events
+    /// of other windows, which should go to the root [`EguiStateHandler`] instead.
+    pub fn process_input(&self, event: &Event) -> bool {
+        let window_id = match event.get_window_id() {
+            Some(window_id) => window_id,
+            None => return false,
+        };
+        let pixels_per_point = self.painter.borrow().pixels_per_point;
+        let mut windows = self.windows.borrow_mut();
+        let (&viewport_id, viewport) = match windows
+            .windows
+            .iter_mut()
+            .find(|(_, viewport)| viewport.window.id() == window_id)
+        {
+            Some(viewport) => viewport,
+            None => return false,
+        };
+
+        let input = &mut viewport.state.input;
+        match event {
+            Event::Window { win_event, .. } => match win_event {
+                WindowEvent::Resized(_, _) | WindowEvent::SizeChanged(_, _) => {
+                    input.screen_rect = Some(screen_rect(&viewport.window, pixels_per_point));
+                }
+                WindowEvent::Close => {
+                    let info = input.viewports.entry(viewport_id).or_default();
+                    info.events.push(ViewportEvent::Close);
+                }
+                WindowEvent::FocusGained | WindowEvent::FocusLost => {
+                    let focused = matches!(win_event, WindowEvent::FocusGained);
+                    input.viewports.entry(viewport_id).or_default().focused = Some(focused);
+                    input.focused = focused;
+                    input.events.push(egui::Event::WindowFocused(focused));
+                }
+                _ => {}
+            },
+            _ => translate_event(
+                &viewport.window,
+                event.clone(),
+                pixels_per_point,
+                (0, 0),
+                &mut viewport.state,
+            ),
+        }
+        true
+    }
+
+    /// Opens windows for new viewports in `viewport_output`, applies their commands, closes the
+    /// windows of viewports egui no longer shows, and runs and paints the deferred viewports
+    /// that have input or asked for a repaint. Call it after painting and swapping the root
+    /// window, with the root viewport's output.
+    pub fn update(&self, ctx: &Context, viewport_output: &ViewportIdMap<ViewportOutput>) {
+        self.sync_windows(viewport_output);
+
+        let now = Instant::now();
+        let due: Vec<(ViewportId, Arc<DeferredViewportUiCallback>)> = self
+            .windows
+            .borrow()
+            .windows
+            .iter()
+            .filter(|(_, viewport)| viewport.class == ViewportClass::Deferred)
+            .filter_map(|(&id, viewport)| {
+                let callback = viewport.viewport_ui_cb.clone()?;
+                let has_input = !viewport.state.input.events.is_empty()
+                    || viewport
+                        .state
+                        .input
+                        .viewports
+                        .get(&id)
+                        .is_some_and(|info| !info.events.is_empty());
+                let repaint = viewport.repaint_at.is_some_and(|at| at <= now);
+                (has_input || repaint).then_some((id, callback))
+            })
+            .collect();
+
+        for (id, callback) in due {
+            if let Some(output) = self.run_viewport(ctx, id, |ctx| callback(ctx)) {
+                let viewport_output = self.paint_viewport(ctx, id, output);
+                self.sync_windows(&viewport_output);
+            }
+        }
+    }
+
+    fn render_immediate(&self, ctx: &Context, viewport: ImmediateViewport<'_>) {
+        let ImmediateViewport {
+            ids,
+            builder,
+            mut viewport_ui_cb,
+        } = viewport;
+        if !self.ensure_window(ids.this, ViewportClass::Immediate, builder) {
+            return;
+        }
+        if let Some(output) = self.run_viewport(ctx, ids.this, |ctx| viewport_ui_cb(ctx)) {
+            // Commands of immediate viewports come with the output of the root viewport.
+            self.paint_viewport(ctx, ids.this, output);
+        }
+    }
+
+    /// Runs the ui of a viewport with the input gathered for its window. No borrows are held
+    /// while it runs, since it may show immediate viewports of its own.
+    fn run_viewport(
+        &self,
+        ctx: &Context,
+        id: ViewportId,
+        mut run_ui: impl FnMut(&Context),
+    ) -> Option<FullOutput> {
+        let input = {
+            let mut windows = self.windows.borrow_mut();
+            let time = windows.start_time.elapsed().as_secs_f64();
+            let viewport = windows.windows.get_mut(&id)?;
+            viewport.repaint_at = None;
+            let mut input = viewport.state.input.take();
+            input.viewport_id = id;
+            input.time = Some(time);
+            input
+        };
+        Some(ctx.run(input, |ctx| run_ui(ctx)))
+    }
+
+    /// Paints the output of a viewport into its window, and returns its viewport output.
+    fn paint_viewport(
+        &self,
+        ctx: &Context,
+        id: ViewportId,
+        output: FullOutput,
+    ) -> ViewportIdMap<ViewportOutput> {
+        let FullOutput {
+            platform_output,
+            textures_delta,
+            shapes,
+            pixels_per_point,
+            mut viewport_output,
+        } = output;
+
+        let mut windows = self.windows.borrow_mut();
+        let windows = &mut *windows;
+        let viewport = match windows.windows.get_mut(&id) {
+            Some(viewport) => viewport,
+            None => return viewport_output,
+        };
+        viewport
+            .state
+            .process_output(&viewport.window, &platform_output);
+        if let Some(output) = viewport_output.get_mut(&id) {
+            // Taken from the frame painted below, so they don't reach `apply_command`.
+            output.commands.retain(|command| match command {
+                ViewportCommand::Screenshot(user_data) => {
+                    viewport.screenshots.push(user_data.clone());
+                    false
+                }
+                _ => true,
+            });
+            if !output.repaint_delay.is_zero() && output.repaint_delay < Duration::MAX {
+                viewport.repaint_at = Some(Instant::now() + output.repaint_delay);
+            } else if output.repaint_delay.is_zero() {
+                viewport.repaint_at = Some(Instant::now());
+            }
+        }
+
+        let mut painter = self.painter.borrow_mut();
+        let root_canvas_size = painter.canvas_size;
+        // A surface set for the root window doesn't apply to the others.
+        let root_surface = painter.surface();
+        painter.set_surface(None);
+        if viewport.window.gl_set_context_to_current().is_ok() {
+            painter.update_screen_rect(viewport.window.drawable_size());
+            let paint_jobs = ctx.tessellate(shapes, pixels_per_point);
+            let bg_color = ctx.style().visuals.panel_fill;
+            painter.paint_jobs(Some(bg_color), textures_delta, paint_jobs);
+            for user_data in viewport.screenshots.drain(..) {
+                let image = painter.read_screen_rgba(painter.screen_rect);
+                viewport.state.input.events.push(egui::Event::Screenshot {
+                    viewport_id: id,
+                    user_data,
+                    image: Arc::new(image),
+                });
+            }
+            viewport.window.gl_swap_window();
+        } else {
+            // Still upload and free textures, they are shared with the other windows.
+            painter.paint_jobs(None, textures_delta, Vec::new());
+        }
+        painter.update_screen_rect(root_canvas_size);
+        painter.set_surface(root_surface);
+        let _ = windows.root_window.gl_set_context_to_current();
+
+        viewport_output
+    }
+
+    /// Makes the set of windows match the viewports in `viewport_output`.
+    fn sync_windows(&self, viewport_output: &ViewportIdMap<ViewportOutput>) {
+        for (&id, output) in viewport_output {
+            if id == ViewportId::ROOT {
+                continue;
+            }
+            if self.ensure_window(id, output.class, output.builder.clone()) {
+                let mut windows = self.windows.borrow_mut();
+                if let Some(viewport) = windows.windows.get_mut(&id) {
+                    viewport.viewport_ui_cb = output.viewport_ui_cb.clone();
+                    for command in &output.commands {
+                        apply_command(viewport, id, command);
+                    }
+                }
+            }
+        }
+
+        // Dropping the window closes it.
+        self.windows
+            .borrow_mut()
+            .windows
+            .retain(|id, _| viewport_output.contains_key(id));
+    }
+
+    /// Opens the window of a viewport, or updates it to a changed builder. Returns whether
+    /// the viewport has a window.
+    fn ensure_window(
+        &self,
+        id: ViewportId,
+        class: ViewportClass,
+        builder: ViewportBuilder,
+    ) -> bool {
+        let pixels_per_point = self.painter.borrow().pixels_per_point;
+        let mut windows = self.windows.borrow_mut();
+        let windows = &mut *windows;
+
+        if let Some(viewport) = windows.windows.get_mut(&id) {
+            let (commands, recreate) = viewport.builder.patch(builder);
+            if !recreate {
+                for command in &commands {
+                    apply_command(viewport, id, command);
+                }
+                return true;
+            }
+            match create_window(&windows.root_window, &viewport.builder, pixels_per_point) {
+                Some(window) => {
+                    viewport.state.input.screen_rect = Some(screen_rect(&window, pixels_per_point));
+                    viewport.window = window;
+                }
+                None => {
+                    windows.windows.remove(&id);
+                    return false;
+                }
+            }
+            return true;
+        }
+
+        let window = match create_window(&windows.root_window, &builder, pixels_per_point) {
+            Some(window) => window,
+            None => return false,
+        };
+        let mut input = egui::RawInput {
+            viewport_id: id,
+            screen_rect: Some(screen_rect(&window, pixels_per_point)),
+            ..Default::default()
+        };
+        input
+            .viewports
+            .entry(id)
+            .or_default()
+            .native_pixels_per_point = Some(pixels_per_point);
+        let state = EguiStateHandler {
+            fused_cursor: FusedCursor::default(),
+            pointer_pos: Pos2::ZERO,
+            input,
+            modifiers: Default::default(),
+            native_pixels_per_point: pixels_per_point,
+        };
+        windows.windows.insert(
+            id,
+            ViewportWindow {
+                window,
+                state,
+                class,
+                builder,
+                viewport_ui_cb: None,
+                // Deferred viewports are first painted by the next `update`.
+                repaint_at: Some(Instant::now()),
+                screenshots: Vec::new(),
+            },
+        );
+        true
+    }
+}
+
+impl ViewportWindow {
+    fn size_in_pixels(size: egui::Vec2, pixels_per_point: f32) -> (u32, u32) {
+        let size = size * pixels_per_point;
+        (size.x.max(1.0) as u32, size.y.max(1.0) as u32)
+    }
+}
+
+fn screen_rect(window: &Window, pixels_per_point: f32) -> Rect {
+    let (width, height) = window.drawable_size();
+    Rect::from_min_size(
+        pos2(0.0, 0.0),
+        vec2(width as f32, height as f32) / pixels_per_point,
+    )
+}
+
+/// Opens an OpenGL window for a viewport, in the GL setup of `root_window`.
+fn create_window(
+    root_window: &Window,
+    builder: &ViewportBuilder,
+    pixels_per_point: f32,
+) -> Option<Window> {
+    let title = builder.title.as_deref().unwrap_or("egui");
+    let (width, height) = ViewportWindow::size_in_pixels(
+        builder
+            .inner_size
+            .unwrap_or_else(|| vec2(DEFAULT_INNER_SIZE.0, DEFAULT_INNER_SIZE.1)),
+        pixels_per_point,
+    );
+
+    let video = root_window.subsystem();
+    let mut window_builder = video.window(title, width, height);
+    window_builder.opengl();
+    match builder.position {
+        Some(position) => {
+            let position = position * pixels_per_point;
+            window_builder.position(position.x as i32, position.y as i32)
+        }
+        None => window_builder.position_centered(),
+    };
+    if builder.resizable != Some(false) {
+        window_builder.resizable();
+    }
+    if builder.decorations == Some(false) {
+        window_builder.borderless();
+    }
+    if builder.visible == Some(false) {
+        window_builder.hidden();
+    }
+    let mut window = window_builder.build().ok()?;
+    if builder.fullscreen == Some(true) {
+        let _ = window.set_fullscreen(FullscreenType::Desktop);
+    }
+    Some(window)
+}
+
+fn apply_command(viewport: &mut ViewportWindow, id: ViewportId, command: &ViewportCommand) {
+    let pixels_per_point = viewport.state.native_pixels_per_point;
+    let window = &mut viewport.window;
+    match command {
+        ViewportCommand::Close => {
+            // Like the root window, closing is up to the app, which stops showing the viewport.
+            let info = viewport.state.input.viewports.entry(id).or_default();
+            info.events.push(ViewportEvent::Close);
+        }
+        ViewportCommand::Title(title) => {
+            let _ = window.set_title(title);
+        }
+        ViewportCommand::Visible(true) => window.show(),
+        ViewportCommand::Visible(false) => window.hide(),
+        ViewportCommand::InnerSize(size) => {
+            let (width, height) = ViewportWindow::size_in_pixels(*size, pixels_per_point);
+            let _ = window.set_size(width, height);
+        }
+        ViewportCommand::OuterPosition(position) => {
+            let position = *position * pixels_per_point;
+            window.set_position(
+                WindowPos::Positioned(position.x as i32),
+                WindowPos::Positioned(position.y as i32),
+            );
+        }
+        ViewportCommand::Minimized(true) => window.minimize(),
+        ViewportCommand::Maximized(true) => window.maximize(),
+        ViewportCommand::Minimized(false) | ViewportCommand::Maximized(false) => window.restore(),
+        ViewportCommand::Fullscreen(fullscreen) => {
+            let fullscreen_type = if *fullscreen {
+                FullscreenType::Desktop
+            } else {
+                FullscreenType::Off
+            };
+            let _ = window.set_fullscreen(fullscreen_type);
+        }
+        ViewportCommand::Focus => window.raise(),
+        ViewportCommand::Screenshot(user_data) => {
+            viewport.screenshots.push(user_data.clone());
+            viewport.repaint_at = Some(Instant::now());
+        }
+        _ => {}
+    }
+}
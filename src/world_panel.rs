@@ -0,0 +1,190 @@
+//! egui panels shown on surfaces inside a 3D world, like terminals or screens in a game.
+//!
+//! A [`WorldPanel`] runs its own [`egui::Context`] and paints it into a texture with its own
+//! [`Painter`], which the app then draws on whatever mesh the panel sits on. The SDL window is
+//! not the surface of the panel, so pointer input comes in as texture coordinates of a ray hit
+//! instead of window coordinates, and keyboard input only reaches the panel while it has focus.
+use crate::painter::{Painter, PainterError};
+use crate::{translate_event, EguiStateHandler, ShaderVersion};
+use egui::{pos2, Color32, Context, FullOutput, OutputCommand, PointerButton, Pos2, Rect, Vec2};
+use gl::types::GLuint;
+use sdl2::event::Event;
+use sdl2::video::Window;
+use std::time::Instant;
+
+/// An egui context painted into a texture, with input routed to it by the app.
+pub struct WorldPanel {
+    ctx: Context,
+    painter: Painter,
+    state: EguiStateHandler,
+    size: (u32, u32),
+    focused: bool,
+    start_time: Instant,
+}
+
+impl WorldPanel {
+    /// Creates a panel painting into a texture of `size` pixels, at `pixels_per_point`.
+    ///
+    /// The GL context of `window` has to be current, and is the one the texture lives in.
+    pub fn new(
+        window: &Window,
+        size: (u32, u32),
+        pixels_per_point: f32,
+        shader_ver: ShaderVersion,
+    ) -> Result<WorldPanel, PainterError> {
+        let mut painter = Painter::try_new(window, pixels_per_point, shader_ver)?;
+        painter.update_screen_rect(size);
+        let mut state = EguiStateHandler::new(&painter);
+        state.input.focused = false;
+        Ok(WorldPanel {
+            ctx: Context::default(),
+            painter,
+            state,
+            size,
+            focused: false,
+            start_time: Instant::now(),
+        })
+    }
+
+    pub fn context(&self) -> &Context {
+        &self.ctx
+    }
+
+    /// The painter of the panel, e.g. for registering user textures the panel shows or
+    /// setting [`Painter::gl_state_restore`].
+    pub fn painter_mut(&mut self) -> &mut Painter {
+        &mut self.painter
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        self.size
+    }
+
+    /// Changes the size of the texture, in pixels.
+    pub fn set_size(&mut self, size: (u32, u32)) {
+        self.size = size;
+        self.painter.update_screen_rect(size);
+        self.state.input.screen_rect = Some(self.painter.screen_rect);
+    }
+
+    /// The texture of the last [`WorldPanel::run`], with the usual GL bottom-left origin.
+    pub fn texture(&self) -> Option<GLuint> {
+        self.painter.render_target_texture()
+    }
+
+    pub fn is_focused(&self) -> bool {
+        self.focused
+    }
+
+    /// Gives the panel keyboard focus, or takes it away. Only one panel should have focus at a
+    /// time, e.g. the one the player last clicked or is looking at.
+    pub fn set_focused(&mut self, focused: bool) {
+        if self.focused != focused {
+            self.focused = focused;
+            self.state.input.focused = focused;
+            self.state
+                .input
+                .events
+                .push(egui::Event::WindowFocused(focused));
+        }
+    }
+
+    /// Moves the pointer to texture coordinates `uv` of the panel, where (0, 0) is the bottom-left
+    /// corner like the texture itself. `None` means the ray no longer hits the panel.
+    pub fn pointer_moved(&mut self, uv: Option<Pos2>) {
+        let event = match uv {
+            Some(uv) => {
+                self.state.pointer_pos = self.uv_to_pos(uv);
+                egui::Event::PointerMoved(self.state.pointer_pos)
+            }
+            None => egui::Event::PointerGone,
+        };
+        self.state.input.events.push(event);
+    }
+
+    /// Presses or releases a pointer button at the last position given to
+    /// [`WorldPanel::pointer_moved`].
+    pub fn pointer_button(&mut self, button: PointerButton, pressed: bool) {
+        self.state.input.events.push(egui::Event::PointerButton {
+            pos: self.state.pointer_pos,
+            button,
+            pressed,
+            modifiers: self.state.modifiers,
+        });
+    }
+
+    /// Scrolls by `delta` points at the pointer.
+    pub fn scroll(&mut self, delta: Vec2) {
+        self.state.input.events.push(egui::Event::MouseWheel {
+            unit: egui::MouseWheelUnit::Point,
+            delta,
+            modifiers: self.state.modifiers,
+        });
+    }
+
+    /// Passes keyboard and text events of `window` to the panel if it has focus. Returns whether
+    /// the event was used, in which case the rest of the app should ignore it.
+    ///
+    /// Pointer events are not used, since they are in window coordinates.
+    pub fn process_input(&mut self, window: &Window, event: &Event) -> bool {
+        if !self.focused || event.get_window_id() != Some(window.id()) {
+            return false;
+        }
+        match event {
+            Event::KeyDown { .. } | Event::KeyUp { .. } | Event::TextInput { .. } => {
+                let pixels_per_point = self.painter.pixels_per_point;
+                translate_event(
+                    window,
+                    event.clone(),
+                    pixels_per_point,
+                    (0, 0),
+                    &mut self.state,
+                );
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Runs the ui of the panel with the input gathered since the last run, paints it and
+    /// returns the texture.
+    ///
+    /// `window` is only used for the clipboard; the panel leaves the mouse cursor alone.
+    pub fn run(
+        &mut self,
+        window: &Window,
+        bg_color: Option<Color32>,
+        run_ui: impl FnMut(&Context),
+    ) -> GLuint {
+        let mut input = self.state.input.take();
+        input.time = Some(self.start_time.elapsed().as_secs_f64());
+        input.screen_rect = Some(self.painter.screen_rect);
+        let FullOutput {
+            platform_output,
+            textures_delta,
+            shapes,
+            pixels_per_point,
+            ..
+        } = self.ctx.run(input, run_ui);
+
+        for command in &platform_output.commands {
+            if let OutputCommand::CopyText(text) = command {
+                if !text.is_empty() {
+                    let _ = window.subsystem().clipboard().set_clipboard_text(text);
+                }
+            }
+        }
+
+        let paint_jobs = self.ctx.tessellate(shapes, pixels_per_point);
+        self.painter
+            .paint_jobs_to_texture(self.size, bg_color, textures_delta, paint_jobs)
+    }
+
+    fn uv_to_pos(&self, uv: Pos2) -> Pos2 {
+        let Rect { min, max } = self.painter.screen_rect;
+        pos2(
+            min.x + uv.x * (max.x - min.x),
+            min.y + (1.0 - uv.y) * (max.y - min.y),
+        )
+    }
+}